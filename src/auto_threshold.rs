@@ -0,0 +1,140 @@
+//! Estimates a threshold from the detector level's distribution over time.
+
+use nih_plug::util;
+
+/// The lowest level tracked by the histogram, in decibels. Anything below this is counted as this
+/// level.
+const MIN_LEVEL_DB: f32 = -100.0;
+/// The histogram's resolution.
+const BINS_PER_DB: f32 = 2.0;
+/// The number of histogram bins between [`MIN_LEVEL_DB`] and 0 dBFS, inclusive. Levels above 0
+/// dBFS are counted in the last bin.
+const NUM_BINS: usize = (-MIN_LEVEL_DB * BINS_PER_DB) as usize + 1;
+/// The fraction of the measured detector levels at or below the noise floor. The key signal is
+/// expected to be silent for a good part of the measurement, so a low percentile reliably lands
+/// on the noise regardless of how loud or how long the actual signal is.
+const NOISE_FLOOR_PERCENTILE: f32 = 0.1;
+
+/// Measures the noise floor and the signal peaks from a stream of detector levels, and proposes a
+/// threshold in between them.
+#[derive(Debug, Clone)]
+pub struct AutoThreshold {
+    /// The number of detector levels that fell into every bin.
+    histogram: [u32; NUM_BINS],
+    /// The total number of detector levels measured.
+    num_levels: u64,
+    /// The highest detector level measured, in decibels.
+    peak_db: f32,
+}
+
+impl Default for AutoThreshold {
+    fn default() -> Self {
+        Self {
+            histogram: [0; NUM_BINS],
+            num_levels: 0,
+            peak_db: MIN_LEVEL_DB,
+        }
+    }
+}
+
+impl AutoThreshold {
+    /// Discard all measurements.
+    pub fn reset(&mut self) {
+        self.histogram.fill(0);
+        self.num_levels = 0;
+        self.peak_db = MIN_LEVEL_DB;
+    }
+
+    /// Measure a single detector level in voltage gain.
+    pub fn next(&mut self, level: f32) {
+        let level_db = util::gain_to_db(level).max(MIN_LEVEL_DB);
+        let bin = (((level_db - MIN_LEVEL_DB) * BINS_PER_DB) as usize).min(NUM_BINS - 1);
+        self.histogram[bin] = self.histogram[bin].saturating_add(1);
+        self.num_levels += 1;
+        self.peak_db = self.peak_db.max(level_db);
+    }
+
+    /// The estimated noise floor in decibels, or `None` if nothing has been measured yet.
+    pub fn noise_floor_db(&self) -> Option<f32> {
+        if self.num_levels == 0 {
+            return None;
+        }
+
+        let target_count = ((self.num_levels as f32) * NOISE_FLOOR_PERCENTILE).ceil() as u64;
+        let mut count = 0;
+        for (bin, bin_count) in self.histogram.iter().enumerate() {
+            count += *bin_count as u64;
+            if count >= target_count {
+                // The upper edge of the bin, so the estimate errs on the loud side
+                return Some(MIN_LEVEL_DB + ((bin + 1) as f32) / BINS_PER_DB);
+            }
+        }
+
+        Some(self.peak_db)
+    }
+
+    /// Propose a threshold `margin_db` decibels above the noise floor. The threshold is kept below
+    /// the halfway point between the noise floor and the loudest peak so the signal still opens
+    /// the gate when the margin is too large for the material. Returns `None` if nothing has been
+    /// measured yet.
+    pub fn propose(&self, margin_db: f32) -> Option<f32> {
+        let noise_floor_db = self.noise_floor_db()?;
+        let halfway_db = (noise_floor_db + self.peak_db) / 2.0;
+
+        Some((noise_floor_db + margin_db).min(halfway_db.max(noise_floor_db)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::{ EnvelopeFollower, DETECTOR_RELEASE_MS };
+    use crate::test_util;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Measure four seconds of white noise at -60 dBFS RMS with a 440 Hz burst at -20 dBFS during
+    /// the first 200 ms of every second, using the same peak envelope follower as the gate.
+    fn measure_noise_and_bursts() -> AutoThreshold {
+        let mut envelope_follower = EnvelopeFollower::default();
+        envelope_follower.set_release(SAMPLE_RATE, DETECTOR_RELEASE_MS);
+        let mut auto_threshold = AutoThreshold::default();
+
+        let noise_amplitude = util::db_to_gain(-60.0) * 3.0f32.sqrt();
+        let burst_amplitude = util::db_to_gain(-20.0);
+        let num_samples = SAMPLE_RATE as usize * 4;
+        for (sample_idx, noise) in test_util::noise().take(num_samples).enumerate() {
+            let time = sample_idx as f32 / SAMPLE_RATE;
+            let burst = if time.fract() < 0.2 {
+                (std::f32::consts::TAU * 440.0 * time).sin()
+            } else {
+                0.0
+            };
+
+            let sample = noise * noise_amplitude + burst * burst_amplitude;
+            auto_threshold.next(envelope_follower.next(sample));
+        }
+
+        auto_threshold
+    }
+
+    #[test]
+    fn threshold_between_noise_and_bursts() {
+        let auto_threshold = measure_noise_and_bursts();
+
+        let noise_floor_db = auto_threshold.noise_floor_db().unwrap();
+        assert!((-58.0..=-54.0).contains(&noise_floor_db), "noise floor: {noise_floor_db} dB");
+
+        let threshold_db = auto_threshold.propose(6.0).unwrap();
+        assert!((-52.0..=-48.0).contains(&threshold_db), "threshold: {threshold_db} dB");
+
+        // A margin that would put the threshold above the bursts is capped
+        let threshold_db = auto_threshold.propose(60.0).unwrap();
+        assert!(threshold_db < -30.0, "capped threshold: {threshold_db} dB");
+    }
+
+    #[test]
+    fn no_proposal_without_measurements() {
+        assert_eq!(AutoThreshold::default().propose(6.0), None);
+    }
+}
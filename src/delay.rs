@@ -0,0 +1,90 @@
+//! A simple ring buffer based delay line for delaying the audio path relative to the detector.
+
+/// A delay line with an integer delay time. The buffer is allocated up front, so changing the delay
+/// time never allocates.
+#[derive(Debug, Clone, Default)]
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    /// The index in `buffer` the next sample will be written to.
+    write_pos: usize,
+}
+
+impl DelayLine {
+    /// Create a delay line that can delay the signal by up to `max_delay` samples.
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 1],
+            write_pos: 0,
+        }
+    }
+
+    /// Clear the delay line's contents.
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_pos = 0;
+    }
+
+    /// Write a sample to the delay line and return the sample from `delay` samples ago. The delay
+    /// is clamped to the `max_delay` passed to [`new()`][Self::new()]. A delay of zero returns
+    /// `sample` as is.
+    pub fn process(&mut self, sample: f32, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        if len == 0 {
+            return sample;
+        }
+
+        self.buffer[self.write_pos] = sample;
+        let read_pos = (self.write_pos + len - delay.min(len - 1)) % len;
+        let result = self.buffer[read_pos];
+        self.write_pos = (self.write_pos + 1) % len;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulse_is_delayed() {
+        for delay in [0, 1, 17, 64] {
+            let mut delay_line = DelayLine::new(64);
+            let output: Vec<f32> = (0..100)
+                .map(|sample_idx| {
+                    let sample = if sample_idx == 10 { 1.0 } else { 0.0 };
+                    delay_line.process(sample, delay)
+                })
+                .collect();
+
+            let mut expected = vec![0.0; 100];
+            expected[10 + delay] = 1.0;
+            assert_eq!(output, expected, "{delay} samples");
+        }
+    }
+
+    #[test]
+    fn delay_is_clamped() {
+        let mut delay_line = DelayLine::new(8);
+        assert_eq!(delay_line.buffer.len(), 9);
+
+        let output: Vec<f32> = (0..20)
+            .map(|sample_idx| delay_line.process(sample_idx as f32, 100))
+            .collect();
+        assert_eq!(&output[..8], &[0.0; 8]);
+        assert_eq!(output[8..], (0..12).map(|sample_idx| sample_idx as f32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reset_clears_the_buffer() {
+        let mut delay_line = DelayLine::new(4);
+        for _ in 0..4 {
+            delay_line.process(1.0, 4);
+        }
+
+        delay_line.reset();
+        for _ in 0..5 {
+            assert_eq!(delay_line.process(0.0, 4), 0.0);
+        }
+    }
+}
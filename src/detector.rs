@@ -0,0 +1,226 @@
+//! Level detection for the gate's key signal and for the editor's meters.
+
+use std::f32::consts::PI;
+
+use crate::DetectorMode;
+
+/// The oversampling factor used for true-peak detection.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+/// The number of taps per polyphase branch of the true-peak interpolation filter.
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+/// The total length of the true-peak interpolation filter.
+const TRUE_PEAK_TAPS: usize = TRUE_PEAK_OVERSAMPLING * TRUE_PEAK_TAPS_PER_PHASE;
+
+/// Computes a level in linear voltage gain from a stream of samples using one of the
+/// [`DetectorMode`]s. The state for all modes is kept up to date regardless of the mode being used,
+/// so switching between modes doesn't cause the level to jump.
+#[derive(Debug, Clone)]
+pub struct Detector {
+    /// Squared input samples for the RMS window. This is allocated for the maximum window size, and
+    /// only the first `rms_window_len` samples are used.
+    rms_window: Vec<f32>,
+    /// The number of samples in the RMS window currently in use.
+    rms_window_len: usize,
+    /// The position in `rms_window` the next squared sample will be written to.
+    rms_window_pos: usize,
+    /// The sum of all squared samples in the RMS window. This is updated incrementally in double
+    /// precision and recomputed from the window every time the window wraps around, so rounding
+    /// errors can't build up over time.
+    rms_sum: f64,
+
+    /// The coefficients for the true-peak interpolation filter, a windowed sinc low-pass at the
+    /// original Nyquist frequency.
+    true_peak_coefficients: [f32; TRUE_PEAK_TAPS],
+    /// The last [`TRUE_PEAK_TAPS_PER_PHASE`] input samples, used as a ring buffer.
+    true_peak_history: [f32; TRUE_PEAK_TAPS_PER_PHASE],
+    /// The index in `true_peak_history` the next sample will be written to.
+    true_peak_history_pos: usize,
+}
+
+impl Detector {
+    /// Create a detector with room for an RMS window of up to `max_rms_window` samples.
+    pub fn new(max_rms_window: usize) -> Self {
+        let max_rms_window = max_rms_window.max(1);
+
+        Self {
+            rms_window: vec![0.0; max_rms_window],
+            rms_window_len: max_rms_window,
+            rms_window_pos: 0,
+            rms_sum: 0.0,
+
+            true_peak_coefficients: true_peak_coefficients(),
+            true_peak_history: [0.0; TRUE_PEAK_TAPS_PER_PHASE],
+            true_peak_history_pos: 0,
+        }
+    }
+
+    /// Change the RMS window's length in samples. This clears the RMS window if the length changes.
+    pub fn set_rms_window(&mut self, window_len: usize) {
+        let window_len = window_len.clamp(1, self.rms_window.len());
+        if window_len != self.rms_window_len {
+            self.rms_window_len = window_len;
+            self.rms_window.fill(0.0);
+            self.rms_window_pos = 0;
+            self.rms_sum = 0.0;
+        }
+    }
+
+    /// Reset the detector to silence.
+    pub fn reset(&mut self) {
+        self.rms_window.fill(0.0);
+        self.rms_window_pos = 0;
+        self.rms_sum = 0.0;
+
+        self.true_peak_history.fill(0.0);
+        self.true_peak_history_pos = 0;
+    }
+
+    /// Feed a sample to the detector and return the current level for `mode`.
+    pub fn next(&mut self, mode: DetectorMode, sample: f32) -> f32 {
+        let squared = sample * sample;
+        self.rms_sum += squared as f64 - self.rms_window[self.rms_window_pos] as f64;
+        self.rms_window[self.rms_window_pos] = squared;
+        self.rms_window_pos = (self.rms_window_pos + 1) % self.rms_window_len;
+        if self.rms_window_pos == 0 {
+            self.rms_sum = self.rms_window[..self.rms_window_len]
+                .iter()
+                .map(|squared| *squared as f64)
+                .sum();
+        }
+
+        self.true_peak_history[self.true_peak_history_pos] = sample;
+        self.true_peak_history_pos = (self.true_peak_history_pos + 1) % TRUE_PEAK_TAPS_PER_PHASE;
+
+        match mode {
+            DetectorMode::Peak => sample.abs(),
+            DetectorMode::Rms => (self.rms_sum.max(0.0) / self.rms_window_len as f64).sqrt() as f32,
+            DetectorMode::TruePeak => self.true_peak(),
+        }
+    }
+
+    /// Compute the highest absolute value among the interpolated samples between the last two
+    /// input samples.
+    fn true_peak(&self) -> f32 {
+        let mut peak = 0.0f32;
+        for phase in 0..TRUE_PEAK_OVERSAMPLING {
+            let mut interpolated = 0.0;
+            for tap in 0..TRUE_PEAK_TAPS_PER_PHASE {
+                // `tap` 0 is the most recent sample
+                let history_idx =
+                    (self.true_peak_history_pos + TRUE_PEAK_TAPS_PER_PHASE - 1 - tap) %
+                    TRUE_PEAK_TAPS_PER_PHASE;
+                interpolated +=
+                    self.true_peak_history[history_idx] *
+                    self.true_peak_coefficients[tap * TRUE_PEAK_OVERSAMPLING + phase];
+            }
+
+            peak = peak.max(interpolated.abs());
+        }
+
+        peak
+    }
+}
+
+/// Design the interpolation filter used for true-peak detection. This is a Blackman windowed sinc
+/// with its cutoff at the original Nyquist frequency and a passband gain equal to the oversampling
+/// factor, so the interpolated samples keep the input's amplitude.
+fn true_peak_coefficients() -> [f32; TRUE_PEAK_TAPS] {
+    let mut coefficients = [0.0; TRUE_PEAK_TAPS];
+    let center = (TRUE_PEAK_TAPS - 1) as f32 / 2.0;
+    for (i, coefficient) in coefficients.iter_mut().enumerate() {
+        let x = (i as f32 - center) / TRUE_PEAK_OVERSAMPLING as f32;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+
+        let phase = (2.0 * PI * i as f32) / (TRUE_PEAK_TAPS - 1) as f32;
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+
+        *coefficient = sinc * window;
+    }
+
+    coefficients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    /// 48 samples per period at 48 kHz.
+    const FREQUENCY: f32 = 1000.0;
+    /// Ten periods of the test signals.
+    const RMS_WINDOW: usize = 480;
+
+    fn sine(sample_idx: usize, amplitude: f32) -> f32 {
+        (std::f32::consts::TAU * FREQUENCY * sample_idx as f32 / SAMPLE_RATE).sin() * amplitude
+    }
+
+    fn square(sample_idx: usize, amplitude: f32) -> f32 {
+        let period = (SAMPLE_RATE / FREQUENCY) as usize;
+        if sample_idx % period < period / 2 { amplitude } else { -amplitude }
+    }
+
+    /// The highest level the detector reports for the signal, after the detector has settled.
+    fn max_level(mode: DetectorMode, signal: impl Fn(usize) -> f32) -> f32 {
+        let mut detector = Detector::new(RMS_WINDOW);
+        let mut max_level = 0.0f32;
+        for sample_idx in 0..(RMS_WINDOW * 4) {
+            let level = detector.next(mode, signal(sample_idx));
+            if sample_idx >= RMS_WINDOW {
+                max_level = max_level.max(level);
+            }
+        }
+
+        max_level
+    }
+
+    fn assert_level(mode: DetectorMode, signal: impl Fn(usize) -> f32, expected: f32) {
+        let level = max_level(mode, signal);
+        assert!((level - expected).abs() < 1e-3, "{mode:?}: {level} != {expected}");
+    }
+
+    #[test]
+    fn sine_levels() {
+        // The samples land exactly on the sine's peaks at this frequency
+        let signal = |sample_idx| sine(sample_idx, 0.5);
+        assert_level(DetectorMode::Peak, signal, 0.5);
+        assert_level(DetectorMode::Rms, signal, 0.5 / 2.0f32.sqrt());
+        assert_level(DetectorMode::TruePeak, signal, 0.5);
+    }
+
+    #[test]
+    fn square_levels() {
+        let signal = |sample_idx| square(sample_idx, 0.5);
+        assert_level(DetectorMode::Peak, signal, 0.5);
+        assert_level(DetectorMode::Rms, signal, 0.5);
+    }
+
+    #[test]
+    fn true_peak_catches_inter_sample_peaks() {
+        // A sine at a quarter of the sample rate, sampled 45 degrees away from its peaks. Every
+        // sample is at 1/sqrt(2) of the actual peak.
+        let quarter_rate_sine = |sample_idx: usize| {
+            (std::f32::consts::FRAC_PI_2 * sample_idx as f32 + std::f32::consts::FRAC_PI_4).sin()
+        };
+        assert_level(DetectorMode::Peak, quarter_rate_sine, 1.0 / 2.0f32.sqrt());
+
+        let true_peak = max_level(DetectorMode::TruePeak, quarter_rate_sine);
+        assert!((true_peak - 1.0).abs() < 0.05, "true peak: {true_peak}");
+    }
+
+    #[test]
+    fn rms_returns_to_silence() {
+        let mut detector = Detector::new(RMS_WINDOW);
+        // A long loud noise passage followed by silence. The squared samples don't add up exactly
+        // in floating point.
+        for sample in test_util::noise().take(RMS_WINDOW * 1000) {
+            detector.next(DetectorMode::Rms, sample);
+        }
+        let mut level = 1.0;
+        for _ in 0..RMS_WINDOW {
+            level = detector.next(DetectorMode::Rms, 0.0);
+        }
+
+        assert_eq!(level, 0.0);
+    }
+}
@@ -0,0 +1,332 @@
+//! Drawing helpers for the plugin's egui editor.
+
+use nih_plug::prelude::{ util, ParamSetter };
+use nih_plug_egui::egui::{ self, Color32, Stroke, Ui };
+use std::sync::atomic::Ordering;
+
+use crate::gate::GateStage;
+use crate::presets::{ factory_presets, Preset, PresetLibrary };
+use crate::telemetry::{ MeterTelemetry, Telemetry, HISTORY_LEN };
+use crate::{ GateMode, Noiseg8Params };
+
+/// The lowest level shown on the meters and in the gain reduction history, in decibels.
+const METER_FLOOR_DB: f32 = -80.0;
+/// The height of the gain reduction history plot, in logical pixels.
+const HISTORY_HEIGHT: f32 = 60.0;
+
+/// The preset browser's state, created together with the editor.
+pub struct PresetBrowser {
+    factory_presets: Vec<Preset>,
+    /// `None` if there is no place to store user presets on this system.
+    library: Option<PresetLibrary>,
+    /// The names of the presets in `library`.
+    user_presets: Vec<String>,
+    /// The preset that was last loaded or saved.
+    selected: Option<SelectedPreset>,
+    /// The name used for saving and renaming presets.
+    name: String,
+    /// The outcome of the last action.
+    status: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SelectedPreset {
+    /// An index into [`PresetBrowser::factory_presets`].
+    Factory(usize),
+    User(String),
+}
+
+impl Default for PresetBrowser {
+    fn default() -> Self {
+        let mut browser = Self {
+            factory_presets: factory_presets(),
+            library: PresetLibrary::user_library(),
+            user_presets: Vec::new(),
+            selected: None,
+            name: String::new(),
+            status: String::new(),
+        };
+        browser.refresh();
+
+        browser
+    }
+}
+
+impl PresetBrowser {
+    /// Reread the list of user presets.
+    fn refresh(&mut self) {
+        match self.library.as_ref().map(PresetLibrary::names) {
+            Some(Ok(names)) => self.user_presets = names,
+            Some(Err(err)) => self.status = format!("Could not list the user presets: {err}"),
+            None => self.status = String::from("User presets are not available on this system"),
+        }
+    }
+
+    fn selected_name(&self) -> &str {
+        match &self.selected {
+            Some(SelectedPreset::Factory(preset_idx)) => &self.factory_presets[*preset_idx].name,
+            Some(SelectedPreset::User(name)) => name,
+            None => "",
+        }
+    }
+
+    /// Load a preset and apply it to the parameters. Returns whether this replaced the noise
+    /// profile.
+    fn load(
+        &mut self,
+        selection: SelectedPreset,
+        params: &Noiseg8Params,
+        setter: &ParamSetter
+    ) -> bool {
+        let preset = match (&selection, &self.library) {
+            (SelectedPreset::Factory(preset_idx), _) => {
+                Ok(self.factory_presets[*preset_idx].clone())
+            }
+            (SelectedPreset::User(name), Some(library)) => library.load(name),
+            (SelectedPreset::User(_), None) => return false,
+        };
+
+        match preset {
+            Ok(preset) => {
+                let noise_profile_loaded = preset.apply(params, setter);
+                self.status = format!("Loaded '{}'", preset.name);
+                self.name = preset.name;
+                self.selected = Some(selection);
+
+                noise_profile_loaded
+            }
+            Err(err) => {
+                self.status = format!("Could not load the preset: {err}");
+                false
+            }
+        }
+    }
+}
+
+/// Draw the preset selector together with the buttons for managing user presets. Returns `true`
+/// if loading a preset replaced the noise profile.
+pub fn preset_browser(
+    ui: &mut Ui,
+    browser: &mut PresetBrowser,
+    params: &Noiseg8Params,
+    setter: &ParamSetter
+) -> bool {
+    let mut selection = None;
+    egui::ComboBox::from_label("Preset")
+        .selected_text(browser.selected_name())
+        .show_ui(ui, |ui| {
+            for (preset_idx, preset) in browser.factory_presets.iter().enumerate() {
+                let preset_selection = SelectedPreset::Factory(preset_idx);
+                let selected = browser.selected.as_ref() == Some(&preset_selection);
+                if ui.selectable_label(selected, &preset.name).clicked() {
+                    selection = Some(preset_selection);
+                }
+            }
+            if !browser.user_presets.is_empty() {
+                ui.separator();
+            }
+            for name in &browser.user_presets {
+                let preset_selection = SelectedPreset::User(name.clone());
+                let selected = browser.selected.as_ref() == Some(&preset_selection);
+                if ui.selectable_label(selected, name).clicked() {
+                    selection = Some(preset_selection);
+                }
+            }
+        });
+    let noise_profile_loaded = match selection {
+        Some(selection) => browser.load(selection, params, setter),
+        None => false,
+    };
+
+    if let Some(library) = browser.library.clone() {
+        let selected_user_preset = match &browser.selected {
+            Some(SelectedPreset::User(name)) => Some(name.clone()),
+            _ => None,
+        };
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut browser.name);
+
+            if ui.button("Save").clicked() {
+                let preset = Preset::from_params(&browser.name, params);
+                browser.status = match library.save(&preset) {
+                    Ok(()) => {
+                        browser.selected = Some(SelectedPreset::User(preset.name));
+                        format!("Saved '{}'", browser.name)
+                    }
+                    Err(err) => format!("Could not save the preset: {err}"),
+                };
+                browser.refresh();
+            }
+
+            let rename = egui::Button::new("Rename");
+            if ui.add_enabled(selected_user_preset.is_some(), rename).clicked() {
+                let old_name = selected_user_preset.as_deref().unwrap_or_default();
+                browser.status = match library.rename(old_name, &browser.name) {
+                    Ok(()) => {
+                        browser.selected = Some(SelectedPreset::User(browser.name.clone()));
+                        format!("Renamed '{old_name}' to '{}'", browser.name)
+                    }
+                    Err(err) => format!("Could not rename the preset: {err}"),
+                };
+                browser.refresh();
+            }
+
+            let delete = egui::Button::new("Delete");
+            if ui.add_enabled(selected_user_preset.is_some(), delete).clicked() {
+                let name = selected_user_preset.as_deref().unwrap_or_default();
+                browser.status = match library.delete(name) {
+                    Ok(()) => {
+                        browser.selected = None;
+                        format!("Deleted '{name}'")
+                    }
+                    Err(err) => format!("Could not delete the preset: {err}"),
+                };
+                browser.refresh();
+            }
+        });
+    }
+
+    if !browser.status.is_empty() {
+        ui.label(&browser.status);
+    }
+
+    noise_profile_loaded
+}
+
+/// Draw the auto threshold's toggle together with its measurement, and apply the threshold it
+/// proposes when it's disarmed. The threshold is set here so the host records it like any other
+/// parameter change.
+pub fn auto_threshold(
+    ui: &mut Ui,
+    params: &Noiseg8Params,
+    setter: &ParamSetter,
+    telemetry: &Telemetry
+) {
+    if let Some(threshold_db) = telemetry.take_proposed_threshold() {
+        setter.begin_set_parameter(&params.threshold);
+        setter.set_parameter(&params.threshold, threshold_db);
+        setter.end_set_parameter(&params.threshold);
+    }
+
+    let mut armed = params.auto_threshold.value();
+    ui.horizontal(|ui| {
+        if ui.checkbox(&mut armed, "Auto threshold").changed() {
+            setter.begin_set_parameter(&params.auto_threshold);
+            setter.set_parameter(&params.auto_threshold, armed);
+            setter.end_set_parameter(&params.auto_threshold);
+        }
+        ui.label(format!("Threshold: {}", params.threshold));
+    });
+
+    if !armed {
+        return;
+    }
+    if params.mode.value() == GateMode::Spectral {
+        ui.label("The auto threshold doesn't measure anything in spectral mode");
+    } else if let Some((noise_floor_db, threshold_db)) = telemetry.auto_threshold_measurement() {
+        ui.label(format!("Noise floor: {noise_floor_db:.1} dBFS"));
+        ui.label(format!("Proposed threshold: {threshold_db:.1} dBFS"));
+    } else {
+        ui.label("Measuring the noise floor...");
+    }
+}
+
+/// Draw a horizontal meter for a level in voltage gain.
+pub fn level_meter(ui: &mut Ui, label: &str, level: f32) -> egui::Response {
+    let level_db = util::gain_to_db(level);
+    let text = if level_db > util::MINUS_INFINITY_DB {
+        format!("{label}: {level_db:.1} dBFS")
+    } else {
+        format!("{label}: -inf dBFS")
+    };
+
+    ui.add(egui::widgets::ProgressBar::new(normalize_db(level_db)).text(text))
+}
+
+/// Draw the output peak meter for every channel.
+pub fn peak_meters(ui: &mut Ui, telemetry: &Telemetry) {
+    for channel_idx in 0..telemetry.num_channels() {
+        peak_meter(ui, &format!("Output {}", channel_idx + 1), &telemetry.peak_meters[channel_idx]);
+    }
+}
+
+/// Draw a single peak meter with its peak-hold marker, followed by a clip indicator that can be
+/// clicked to reset it.
+fn peak_meter(ui: &mut Ui, label: &str, meter: &MeterTelemetry) {
+    ui.horizontal(|ui| {
+        let response = level_meter(ui, label, meter.level.load(Ordering::Relaxed));
+
+        let peak_hold_db = util::gain_to_db(meter.peak_hold.load(Ordering::Relaxed));
+        if peak_hold_db > METER_FLOOR_DB {
+            let rect = response.rect;
+            let x = rect.left() + normalize_db(peak_hold_db) * rect.width();
+            ui.painter().vline(x, rect.y_range(), Stroke::new(2.0, Color32::WHITE));
+        }
+
+        let clipped = meter.clipped.load(Ordering::Relaxed);
+        let clip_color = if clipped { Color32::RED } else { ui.visuals().weak_text_color() };
+        let clip_indicator = egui::Button::new(egui::RichText::new("CLIP").color(clip_color));
+        if ui.add(clip_indicator).clicked() {
+            meter.clipped.store(false, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Draw the latest telemetry for every channel, followed by a plot of that channel's gain reduction
+/// history.
+pub fn telemetry_view(ui: &mut Ui, telemetry: &Telemetry) {
+    for channel_idx in 0..telemetry.num_channels() {
+        let frame = telemetry.latest(channel_idx).unwrap_or_default();
+
+        ui.label(format!("Channel {}: {}", channel_idx + 1, stage_name(frame.stage)));
+        level_meter(ui, "Input", frame.input_level);
+        level_meter(ui, "Detector", frame.detector_level);
+        level_meter(ui, "Output", frame.output_level);
+        ui.label(format!("Gain reduction: {:.1} dB", util::gain_to_db(frame.gain)));
+        gain_reduction_history(ui, telemetry, channel_idx);
+    }
+}
+
+/// Plot a channel's gain reduction over time. The most recent frame is on the right, and 0 dB of
+/// gain reduction is at the top.
+fn gain_reduction_history(ui: &mut Ui, telemetry: &Telemetry, channel_idx: usize) {
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(ui.available_width(), HISTORY_HEIGHT),
+        egui::Sense::hover()
+    );
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let mut gains_db = Vec::with_capacity(HISTORY_LEN);
+    telemetry.for_each_in_history(channel_idx, |frame| gains_db.push(util::gain_to_db(frame.gain)));
+
+    let x_step = rect.width() / (HISTORY_LEN - 1) as f32;
+    let points = gains_db
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(age, gain_db)| {
+            egui::pos2(
+                rect.right() - (age as f32) * x_step,
+                rect.top() + (1.0 - normalize_db(*gain_db)) * rect.height()
+            )
+        })
+        .collect();
+    painter.add(egui::Shape::line(points, Stroke::new(1.5, Color32::LIGHT_BLUE)));
+}
+
+/// Map a level in decibels to `[0, 1]` for the meters.
+fn normalize_db(level_db: f32) -> f32 {
+    ((level_db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0)
+}
+
+fn stage_name(stage: GateStage) -> &'static str {
+    match stage {
+        GateStage::Closed => "Closed",
+        GateStage::Attack => "Attack",
+        GateStage::Open => "Open",
+        GateStage::Hold => "Hold",
+        GateStage::Release => "Release",
+    }
+}
@@ -0,0 +1,277 @@
+//! A downward expander, used as a gentler alternative to the gate.
+
+use nih_plug::util;
+
+use crate::gate::{ ms_to_samples, GateStage };
+
+/// The ratio at and above which the expander's ratio is treated as infinite.
+pub const MAX_RATIO: f32 = 100.0;
+
+/// How close the gain needs to be to unity or to the range floor for the expander to report itself
+/// as fully open or closed.
+const STAGE_TOLERANCE_DB: f32 = 0.1;
+
+/// A downward expander driven by a detector level. Like [`Gate`][crate::gate::Gate] this only
+/// computes the gain, applying it to the signal is up to the caller. The gain follows the static
+/// curve from [`expander_gain_db()`] with exponential attack and release smoothing, and a hold time
+/// before the gain starts decreasing.
+#[derive(Debug, Clone, Copy)]
+pub struct Expander {
+    /// The current smoothed gain, in decibels.
+    gain_db: f32,
+    /// The number of samples left before the gain is allowed to decrease again.
+    hold_samples_remaining: u32,
+    /// The gate stage that most closely matches what the expander is currently doing. Only used
+    /// for display purposes.
+    stage: GateStage,
+
+    threshold_db: f32,
+    ratio: f32,
+    knee_db: f32,
+    range_db: f32,
+    /// The weight the previous gain gets on every sample when the gain increases.
+    attack_weight: f32,
+    /// The hold time, in samples.
+    hold_samples: u32,
+    /// The weight the previous gain gets on every sample when the gain decreases.
+    release_weight: f32,
+}
+
+impl Default for Expander {
+    fn default() -> Self {
+        Self {
+            gain_db: util::MINUS_INFINITY_DB,
+            hold_samples_remaining: 0,
+            stage: GateStage::Closed,
+
+            threshold_db: 0.0,
+            ratio: 1.0,
+            knee_db: 0.0,
+            range_db: util::MINUS_INFINITY_DB,
+            attack_weight: 0.0,
+            hold_samples: 0,
+            release_weight: 0.0,
+        }
+    }
+}
+
+impl Expander {
+    /// Set the static curve's parameters. See [`expander_gain_db()`].
+    pub fn set_curve(&mut self, threshold_db: f32, ratio: f32, knee_db: f32, range_db: f32) {
+        self.threshold_db = threshold_db;
+        self.ratio = ratio;
+        self.knee_db = knee_db;
+        self.range_db = range_db;
+    }
+
+    /// Set the attack, hold, and release times. The attack and release times are time constants for
+    /// the exponential gain smoothing.
+    pub fn set_timing(&mut self, sample_rate: f32, attack_ms: f32, hold_ms: f32, release_ms: f32) {
+        self.attack_weight = smoothing_weight(ms_to_samples(sample_rate, attack_ms));
+        self.hold_samples = ms_to_samples(sample_rate, hold_ms).round() as u32;
+        self.release_weight = smoothing_weight(ms_to_samples(sample_rate, release_ms));
+    }
+
+    /// The gate stage that most closely matches what the expander is currently doing. It's only
+    /// in the attack stage while the detector level is above the threshold, and it's closed once
+    /// the gain has settled below unity.
+    pub fn stage(&self) -> GateStage {
+        self.stage
+    }
+
+    /// Fully close the expander. Like the gate, it opens up again once there's a signal.
+    pub fn reset(&mut self) {
+        self.gain_db = self.range_db;
+        self.hold_samples_remaining = 0;
+        self.stage = GateStage::Closed;
+    }
+
+    /// Advance the expander by one sample using the current detector level, and return the gain
+    /// that should be applied to the corresponding output sample.
+    pub fn next(&mut self, level: f32) -> f32 {
+        let target_gain_db = expander_gain_db(
+            util::gain_to_db(level),
+            self.threshold_db,
+            self.ratio,
+            self.knee_db,
+            self.range_db
+        );
+
+        let rising = target_gain_db >= self.gain_db;
+        let holding = !rising && self.hold_samples_remaining > 0;
+        if rising {
+            self.gain_db = target_gain_db + (self.gain_db - target_gain_db) * self.attack_weight;
+            self.hold_samples_remaining = self.hold_samples;
+        } else if holding {
+            self.hold_samples_remaining -= 1;
+        } else {
+            self.gain_db = target_gain_db + (self.gain_db - target_gain_db) * self.release_weight;
+        }
+
+        // The expander only counts as opening while it's heading for unity gain, so a level that
+        // stays below the threshold never opens it
+        self.stage = if self.gain_db >= -STAGE_TOLERANCE_DB {
+            GateStage::Open
+        } else if holding {
+            GateStage::Hold
+        } else if rising && target_gain_db >= -STAGE_TOLERANCE_DB {
+            GateStage::Attack
+        } else if !rising && self.gain_db - target_gain_db > STAGE_TOLERANCE_DB {
+            GateStage::Release
+        } else {
+            GateStage::Closed
+        };
+
+        util::db_to_gain(self.gain_db)
+    }
+}
+
+/// The downward expander's static transfer function. Returns the gain in decibels for an input
+/// level in decibels. Above the threshold the gain is 0 dB, below it every decibel the input drops
+/// lowers the output by `ratio` decibels. The `knee_db` wide region centered on the threshold
+/// interpolates between the two quadratically. The attenuation never exceeds `range_db`, which
+/// should be negative. A ratio of [`MAX_RATIO`] or higher acts as an infinite ratio.
+pub fn expander_gain_db(
+    input_db: f32,
+    threshold_db: f32,
+    ratio: f32,
+    knee_db: f32,
+    range_db: f32
+) -> f32 {
+    let ratio = if ratio >= MAX_RATIO { f32::INFINITY } else { ratio.max(1.0) };
+    let knee_db = knee_db.max(0.0);
+    let half_knee_db = knee_db / 2.0;
+
+    if input_db >= threshold_db + half_knee_db {
+        return 0.0;
+    }
+
+    let gain_db = if input_db > threshold_db - half_knee_db {
+        let knee_distance = input_db - threshold_db - half_knee_db;
+        -(ratio - 1.0) * knee_distance * knee_distance / (2.0 * knee_db)
+    } else {
+        (input_db - threshold_db) * (ratio - 1.0)
+    };
+
+    gain_db.max(range_db)
+}
+
+/// The weight the previous value gets in a one pole smoother with a time constant of `samples`
+/// samples.
+fn smoothing_weight(samples: f32) -> f32 {
+    if samples <= 0.0 { 0.0 } else { (-1.0 / samples).exp() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD_DB: f32 = -40.0;
+    const RANGE_DB: f32 = -80.0;
+
+    fn gain_db(input_db: f32, ratio: f32, knee_db: f32) -> f32 {
+        expander_gain_db(input_db, THRESHOLD_DB, ratio, knee_db, RANGE_DB)
+    }
+
+    #[test]
+    fn starts_closed() {
+        let mut expander = Expander::default();
+        expander.set_curve(THRESHOLD_DB, 2.0, 6.0, RANGE_DB);
+        expander.set_timing(1000.0, 1.0, 10.0, 10.0);
+        assert_eq!(expander.stage(), GateStage::Closed);
+
+        // Silence keeps it closed, both before and after a reset
+        for _ in 0..2 {
+            for _ in 0..100 {
+                expander.next(0.0);
+                assert_eq!(expander.stage(), GateStage::Closed);
+            }
+            expander.reset();
+        }
+    }
+
+    #[test]
+    fn stages_follow_a_burst() {
+        let mut expander = Expander::default();
+        expander.set_curve(THRESHOLD_DB, 2.0, 6.0, RANGE_DB);
+        expander.set_timing(1000.0, 1.0, 10.0, 10.0);
+
+        let burst_level = util::db_to_gain(-20.0);
+        let mut stages = vec![expander.stage()];
+        for sample_idx in 0..300 {
+            let level = if (50..100).contains(&sample_idx) { burst_level } else { 0.0 };
+            expander.next(level);
+            if stages.last() != Some(&expander.stage()) {
+                stages.push(expander.stage());
+            }
+        }
+
+        assert_eq!(stages, [
+            GateStage::Closed,
+            GateStage::Attack,
+            // The gain stays at unity during the hold time
+            GateStage::Open,
+            GateStage::Release,
+            GateStage::Closed,
+        ]);
+    }
+
+    #[test]
+    fn unity_gain_above_the_knee() {
+        for input_db in [-34.0, -20.0, 0.0, 6.0] {
+            assert_eq!(gain_db(input_db, 4.0, 12.0), 0.0, "{input_db} dB");
+        }
+        // Without a knee the threshold itself is already above it
+        assert_eq!(gain_db(THRESHOLD_DB, 4.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn slope_below_the_knee() {
+        // Every decibel below the threshold lowers the output by the ratio, which means the gain
+        // drops by `ratio - 1` decibels
+        for ratio in [1.5, 2.0, 4.0] {
+            let gain_1 = gain_db(-50.0, ratio, 12.0);
+            let gain_2 = gain_db(-60.0, ratio, 12.0);
+            assert!((gain_1 - -10.0 * (ratio - 1.0)).abs() < 1e-4, "ratio {ratio}: {gain_1} dB");
+            assert!((gain_1 - gain_2 - 10.0 * (ratio - 1.0)).abs() < 1e-4, "ratio {ratio}");
+        }
+
+        // A ratio of 1 never attenuates
+        assert_eq!(gain_db(-70.0, 1.0, 12.0), 0.0);
+    }
+
+    #[test]
+    fn knee_is_continuous() {
+        let ratio = 4.0;
+        let knee_db = 12.0;
+        for edge_db in [THRESHOLD_DB - knee_db / 2.0, THRESHOLD_DB + knee_db / 2.0] {
+            let below = gain_db(edge_db - 1e-3, ratio, knee_db);
+            let above = gain_db(edge_db + 1e-3, ratio, knee_db);
+            assert!((below - above).abs() < 1e-2, "{edge_db} dB: {below} dB vs {above} dB");
+        }
+
+        // The knee sits between the two straight segments
+        let knee_gain_db = gain_db(THRESHOLD_DB, ratio, knee_db);
+        assert!(knee_gain_db < 0.0 && knee_gain_db > -(ratio - 1.0) * knee_db / 2.0);
+    }
+
+    #[test]
+    fn attenuation_is_clamped_to_the_range() {
+        assert_eq!(gain_db(-100.0, 4.0, 0.0), RANGE_DB);
+        assert_eq!(gain_db(-70.0, 4.0, 0.0), RANGE_DB);
+        assert_eq!(gain_db(-60.0, 2.0, 0.0), -20.0);
+    }
+
+    #[test]
+    fn max_ratio_acts_as_infinity() {
+        for ratio in [MAX_RATIO, MAX_RATIO * 10.0] {
+            // Anything below the threshold is attenuated by the full range, even just below it
+            assert_eq!(gain_db(THRESHOLD_DB - 0.01, ratio, 0.0), RANGE_DB, "ratio {ratio}");
+            assert_eq!(gain_db(THRESHOLD_DB - 6.0, ratio, 12.0), RANGE_DB, "ratio {ratio}");
+            assert_eq!(gain_db(THRESHOLD_DB, ratio, 0.0), 0.0, "ratio {ratio}");
+        }
+
+        // Just below the maximum the ratio is still finite
+        assert!(gain_db(THRESHOLD_DB - 0.01, MAX_RATIO - 1.0, 0.0) > RANGE_DB);
+    }
+}
@@ -0,0 +1,258 @@
+//! Biquad filters used to band-limit the gate's detector signal and to build the multiband
+//! crossovers.
+
+use std::f32::consts::PI;
+
+/// The maximum number of cascaded biquads per filter. Four sections gives a 48 dB/octave slope.
+pub const MAX_STAGES: usize = 4;
+
+/// Coefficients for a [`Biquad`], normalized so `a0` is 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+/// A biquad filter in transposed direct form II.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    pub coefficients: BiquadCoefficients,
+    s1: f32,
+    s2: f32,
+}
+
+/// A high-pass and a low-pass filter in series, each made out of up to [`MAX_STAGES`] cascaded
+/// Butterworth sections. Disabled filters are skipped entirely.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyFilter {
+    highpass: [Biquad; MAX_STAGES],
+    lowpass: [Biquad; MAX_STAGES],
+    /// The number of active high-pass stages. Zero when the high-pass filter is disabled.
+    highpass_stages: usize,
+    /// The number of active low-pass stages. Zero when the low-pass filter is disabled.
+    lowpass_stages: usize,
+}
+
+impl Default for BiquadCoefficients {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl BiquadCoefficients {
+    /// Coefficients that pass the signal through unchanged.
+    pub const fn identity() -> Self {
+        Self {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+        }
+    }
+
+    /// A second order low-pass filter, based on the equations from the Audio EQ Cookbook.
+    pub fn lowpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos_omega, alpha) = Self::omega_alpha(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: ((1.0 - cos_omega) / 2.0) / a0,
+            b1: (1.0 - cos_omega) / a0,
+            b2: ((1.0 - cos_omega) / 2.0) / a0,
+            a1: (-2.0 * cos_omega) / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// A second order high-pass filter, based on the equations from the Audio EQ Cookbook.
+    pub fn highpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos_omega, alpha) = Self::omega_alpha(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: ((1.0 + cos_omega) / 2.0) / a0,
+            b1: -(1.0 + cos_omega) / a0,
+            b2: ((1.0 + cos_omega) / 2.0) / a0,
+            a1: (-2.0 * cos_omega) / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// A second order all-pass filter, based on the equations from the Audio EQ Cookbook. With a Q
+    /// of `1 / sqrt(2)` this has the same phase response as a fourth order Linkwitz-Riley crossover
+    /// at the same frequency.
+    pub fn allpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos_omega, alpha) = Self::omega_alpha(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 - alpha) / a0,
+            b1: (-2.0 * cos_omega) / a0,
+            b2: (1.0 + alpha) / a0,
+            a1: (-2.0 * cos_omega) / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// Compute `cos(omega)` and `alpha` for the cookbook filters. The frequency is clamped to stay
+    /// below the Nyquist frequency.
+    fn omega_alpha(sample_rate: f32, frequency: f32, q: f32) -> (f32, f32) {
+        let frequency = frequency.clamp(1.0, sample_rate * 0.49);
+        let omega = 2.0 * PI * (frequency / sample_rate);
+        let (sin_omega, cos_omega) = omega.sin_cos();
+
+        (cos_omega, sin_omega / (2.0 * q))
+    }
+}
+
+impl Default for Biquad {
+    fn default() -> Self {
+        Self {
+            coefficients: BiquadCoefficients::identity(),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+}
+
+impl Biquad {
+    /// Process a single sample.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let coefficients = &self.coefficients;
+        let result = coefficients.b0 * sample + self.s1;
+
+        self.s1 = coefficients.b1 * sample - coefficients.a1 * result + self.s2;
+        self.s2 = coefficients.b2 * sample - coefficients.a2 * result;
+
+        result
+    }
+
+    /// Clear the filter's delay line.
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+impl KeyFilter {
+    /// Configure the high-pass filter. `num_stages` is the number of cascaded second order
+    /// sections, so the slope is `12 * num_stages` dB/octave. Passing zero disables the filter.
+    pub fn set_highpass(&mut self, sample_rate: f32, frequency: f32, num_stages: usize) {
+        self.highpass_stages = num_stages.min(MAX_STAGES);
+        for (stage_idx, biquad) in self.highpass[..self.highpass_stages].iter_mut().enumerate() {
+            let q = butterworth_q(self.highpass_stages, stage_idx);
+            biquad.coefficients = BiquadCoefficients::highpass(sample_rate, frequency, q);
+        }
+    }
+
+    /// Configure the low-pass filter. See [`set_highpass()`][Self::set_highpass()].
+    pub fn set_lowpass(&mut self, sample_rate: f32, frequency: f32, num_stages: usize) {
+        self.lowpass_stages = num_stages.min(MAX_STAGES);
+        for (stage_idx, biquad) in self.lowpass[..self.lowpass_stages].iter_mut().enumerate() {
+            let q = butterworth_q(self.lowpass_stages, stage_idx);
+            biquad.coefficients = BiquadCoefficients::lowpass(sample_rate, frequency, q);
+        }
+    }
+
+    /// Clear all of the filters' delay lines.
+    pub fn reset(&mut self) {
+        for biquad in self.highpass.iter_mut().chain(self.lowpass.iter_mut()) {
+            biquad.reset();
+        }
+    }
+
+    /// Process a single sample.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let mut sample = sample;
+        for biquad in &mut self.highpass[..self.highpass_stages] {
+            sample = biquad.process(sample);
+        }
+        for biquad in &mut self.lowpass[..self.lowpass_stages] {
+            sample = biquad.process(sample);
+        }
+
+        sample
+    }
+}
+
+/// The Q value for one of the second order sections making up a Butterworth filter built from
+/// `num_stages` cascaded biquads.
+fn butterworth_q(num_stages: usize, stage_idx: usize) -> f32 {
+    let order = (num_stages * 2) as f32;
+    let angle = PI * ((stage_idx * 2 + 1) as f32) / (2.0 * order);
+
+    1.0 / (2.0 * angle.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const CUTOFF: f32 = 1000.0;
+
+    /// The filter's gain in decibels for a sine at `frequency`, measured as the RMS ratio over
+    /// whole periods after the filter has settled.
+    fn gain_db(key_filter: &mut KeyFilter, frequency: f32) -> f32 {
+        let sine = |sample_idx: usize| {
+            (2.0 * PI * frequency * sample_idx as f32 / SAMPLE_RATE).sin()
+        };
+        let settle_samples = SAMPLE_RATE as usize / 2;
+        for sample_idx in 0..settle_samples {
+            key_filter.process(sine(sample_idx));
+        }
+
+        let measure_samples = SAMPLE_RATE as usize / 2;
+        let (mut input_sum, mut output_sum) = (0.0f64, 0.0f64);
+        for sample_idx in settle_samples..settle_samples + measure_samples {
+            let input = sine(sample_idx);
+            let output = key_filter.process(input);
+            input_sum += (input * input) as f64;
+            output_sum += (output * output) as f64;
+        }
+
+        (10.0 * (output_sum / input_sum).log10()) as f32
+    }
+
+    #[test]
+    fn cutoff_is_minus_3_db() {
+        for num_stages in 1..=MAX_STAGES {
+            let mut highpass = KeyFilter::default();
+            highpass.set_highpass(SAMPLE_RATE, CUTOFF, num_stages);
+            let mut lowpass = KeyFilter::default();
+            lowpass.set_lowpass(SAMPLE_RATE, CUTOFF, num_stages);
+
+            for (name, key_filter) in [("high-pass", &mut highpass), ("low-pass", &mut lowpass)] {
+                let gain_db = gain_db(key_filter, CUTOFF);
+                assert!(
+                    (gain_db + 3.01).abs() <= 0.05,
+                    "{name}, {num_stages} stages: {gain_db} dB"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn butterworth_qs() {
+        // A single section is a second order Butterworth filter
+        assert!((butterworth_q(1, 0) - std::f32::consts::FRAC_1_SQRT_2).abs() <= 1e-6);
+        // The sections of a fourth order filter
+        assert!((butterworth_q(2, 0) - 1.306_563).abs() <= 1e-5);
+        assert!((butterworth_q(2, 1) - 0.541_196).abs() <= 1e-5);
+    }
+
+    #[test]
+    fn zero_stages_is_bit_identical() {
+        let mut key_filter = KeyFilter::default();
+        key_filter.set_highpass(SAMPLE_RATE, CUTOFF, 0);
+        key_filter.set_lowpass(SAMPLE_RATE, CUTOFF, 0);
+        for sample in test_util::noise().take(10000) {
+            assert_eq!(key_filter.process(sample).to_bits(), sample.to_bits());
+        }
+    }
+}
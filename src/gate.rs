@@ -0,0 +1,330 @@
+//! The gate's envelope follower and its open/close state machine.
+
+/// The time constant for the detector's envelope follower. This smooths over zero crossings so the
+/// gate doesn't flutter on low frequency material.
+pub const DETECTOR_RELEASE_MS: f32 = 10.0;
+
+/// The stage the gate is currently in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateStage {
+    /// The detector level is below the threshold and the gain is at the range floor.
+    Closed,
+    /// The gate is ramping up towards unity gain.
+    Attack,
+    /// The gate is fully open.
+    Open,
+    /// The detector level dropped below the threshold, but the gate is kept open for the hold time.
+    Hold,
+    /// The gate is ramping back down to the range floor.
+    Release,
+}
+
+/// A peak envelope follower with an instantaneous attack and an exponential release.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvelopeFollower {
+    /// The weight the previous envelope value gets on every sample when the signal is decaying.
+    release_weight: f32,
+    /// The current envelope value, as voltage gain.
+    envelope: f32,
+}
+
+/// A gate driven by a detector level. This only computes the gain, applying it to the signal is up
+/// to the caller. All levels and gains are in linear voltage gain.
+#[derive(Debug, Clone, Copy)]
+pub struct Gate {
+    stage: GateStage,
+    /// How far the gate is opened, where 0 means that the gain is at the range floor and 1 means
+    /// unity gain.
+    openness: f32,
+    /// The number of samples left before the gate starts releasing. Only used in
+    /// [`GateStage::Hold`].
+    hold_samples_remaining: u32,
+
+    /// The detector level at or above which the gate opens.
+    open_threshold: f32,
+    /// The detector level below which an open gate starts closing. This is at most
+    /// `open_threshold`, and the gap between the two prevents the gate from chattering when the
+    /// signal hovers around the threshold.
+    close_threshold: f32,
+    /// The gain applied while the gate is closed.
+    floor: f32,
+    /// How much `openness` increases every sample during the attack stage.
+    attack_step: f32,
+    /// The hold time, in samples.
+    hold_samples: u32,
+    /// How much `openness` decreases every sample during the release stage.
+    release_step: f32,
+}
+
+impl EnvelopeFollower {
+    /// Recompute the release coefficient. Needs to be called whenever the sample rate changes.
+    pub fn set_release(&mut self, sample_rate: f32, release_ms: f32) {
+        self.release_weight = (-1.0 / (release_ms / 1000.0 * sample_rate)).exp();
+    }
+
+    /// Reset the envelope to silence.
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    /// Feed a sample to the envelope follower and return the new envelope value.
+    pub fn next(&mut self, sample: f32) -> f32 {
+        // The envelope never drops below the current sample, otherwise a constant signal would
+        // make the envelope alternate between two values
+        self.envelope = sample.abs().max(self.envelope * self.release_weight);
+
+        self.envelope
+    }
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self {
+            stage: GateStage::Closed,
+            openness: 0.0,
+            hold_samples_remaining: 0,
+
+            open_threshold: 1.0,
+            close_threshold: 1.0,
+            floor: 0.0,
+            attack_step: 1.0,
+            hold_samples: 0,
+            release_step: 1.0,
+        }
+    }
+}
+
+impl Gate {
+    /// Set the detector levels the gate opens and closes at. The close threshold is clamped so it
+    /// never exceeds the open threshold.
+    pub fn set_thresholds(&mut self, open_threshold: f32, close_threshold: f32) {
+        self.open_threshold = open_threshold;
+        self.close_threshold = close_threshold.min(open_threshold);
+    }
+
+    /// Set the gain applied while the gate is fully closed.
+    pub fn set_floor(&mut self, floor: f32) {
+        self.floor = floor;
+    }
+
+    /// The gain applied while the gate is fully closed.
+    pub fn floor(&self) -> f32 {
+        self.floor
+    }
+
+    /// Set the attack, hold, and release times. The attack and release times are the time it takes
+    /// to go from fully closed to fully open and the other way around.
+    pub fn set_timing(&mut self, sample_rate: f32, attack_ms: f32, hold_ms: f32, release_ms: f32) {
+        self.attack_step = ms_to_samples(sample_rate, attack_ms).max(1.0).recip();
+        self.hold_samples = ms_to_samples(sample_rate, hold_ms).round() as u32;
+        self.release_step = ms_to_samples(sample_rate, release_ms).max(1.0).recip();
+    }
+
+    /// The gate's current stage.
+    pub fn stage(&self) -> GateStage {
+        self.stage
+    }
+
+    /// Fully close the gate.
+    pub fn reset(&mut self) {
+        self.stage = GateStage::Closed;
+        self.openness = 0.0;
+        self.hold_samples_remaining = 0;
+    }
+
+    /// Advance the gate by one sample using the current detector level, and return the gain that
+    /// should be applied to the corresponding output sample.
+    pub fn next(&mut self, level: f32) -> f32 {
+        // Opening the gate requires the higher threshold, while keeping it open only requires the
+        // level to stay above the lower one
+        let above_open_threshold = level >= self.open_threshold;
+        let above_close_threshold = level >= self.close_threshold;
+        match self.stage {
+            GateStage::Closed | GateStage::Release if above_open_threshold => {
+                self.stage = GateStage::Attack;
+            }
+            GateStage::Open if !above_close_threshold => {
+                self.stage = GateStage::Hold;
+                self.hold_samples_remaining = self.hold_samples;
+            }
+            GateStage::Hold if above_close_threshold => {
+                self.stage = GateStage::Open;
+            }
+            _ => (),
+        }
+
+        match self.stage {
+            GateStage::Attack => {
+                self.openness += self.attack_step;
+                if self.openness >= 1.0 {
+                    self.openness = 1.0;
+                    self.stage = GateStage::Open;
+                }
+            }
+            GateStage::Hold => {
+                if self.hold_samples_remaining == 0 {
+                    self.stage = GateStage::Release;
+                } else {
+                    self.hold_samples_remaining -= 1;
+                }
+            }
+            GateStage::Release => {
+                self.openness -= self.release_step;
+                if self.openness <= 0.0 {
+                    self.openness = 0.0;
+                    self.stage = GateStage::Closed;
+                }
+            }
+            GateStage::Closed | GateStage::Open => (),
+        }
+
+        self.floor + (1.0 - self.floor) * self.openness
+    }
+}
+
+/// Convert a time in milliseconds to a (fractional) number of samples.
+pub fn ms_to_samples(sample_rate: f32, ms: f32) -> f32 {
+    ms / 1000.0 * sample_rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// At this sample rate every millisecond is exactly one sample.
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    /// A gate that takes 4 samples to open, holds for 10 samples, and takes 8 samples to close.
+    /// The attack and release steps are exact in floating point.
+    fn test_gate(open_threshold: f32, close_threshold: f32) -> Gate {
+        let mut gate = Gate::default();
+        gate.set_thresholds(open_threshold, close_threshold);
+        gate.set_timing(SAMPLE_RATE, 4.0, 10.0, 8.0);
+
+        gate
+    }
+
+    /// Run the gate over the detector levels, and return the sample indices where the gate
+    /// changed its stage together with the new stage.
+    fn stage_changes(
+        gate: &mut Gate,
+        levels: impl IntoIterator<Item = f32>
+    ) -> Vec<(usize, GateStage)> {
+        let mut changes = Vec::new();
+        let mut stage = gate.stage();
+        for (sample_idx, level) in levels.into_iter().enumerate() {
+            gate.next(level);
+            if gate.stage() != stage {
+                stage = gate.stage();
+                changes.push((sample_idx, stage));
+            }
+        }
+
+        changes
+    }
+
+    #[test]
+    fn burst_timing() {
+        let mut envelope_follower = EnvelopeFollower::default();
+        envelope_follower.set_release(SAMPLE_RATE, DETECTOR_RELEASE_MS);
+        let mut gate = test_gate(0.5, 0.5);
+
+        // A full scale burst from sample 100 to sample 199. The envelope decays by a factor of
+        // `e^-0.1` per sample afterwards, so it first drops below the threshold at sample 206.
+        let levels = (0..300).map(|sample_idx| {
+            let sample = if (100..200).contains(&sample_idx) { 1.0 } else { 0.0 };
+            envelope_follower.next(sample)
+        });
+        assert_eq!(stage_changes(&mut gate, levels), [
+            (100, GateStage::Attack),
+            (103, GateStage::Open),
+            (206, GateStage::Hold),
+            (216, GateStage::Release),
+            (224, GateStage::Closed),
+        ]);
+    }
+
+    #[test]
+    fn gain_follows_the_stages() {
+        let mut gate = test_gate(0.5, 0.5);
+        gate.set_floor(0.2);
+
+        let mut gains = Vec::new();
+        for level in [1.0; 5].into_iter().chain([0.0; 20]) {
+            gains.push(gate.next(level));
+        }
+
+        // Four attack steps, one sample at unity gain, ten hold samples plus the sample that
+        // switches to the release, and eight release steps
+        let openness = [0.25, 0.5, 0.75, 1.0, 1.0]
+            .into_iter()
+            .chain([1.0; 11])
+            .chain([0.875, 0.75, 0.625, 0.5, 0.375, 0.25, 0.125, 0.0])
+            .chain([0.0]);
+        let expected: Vec<f32> = openness.map(|openness| 0.2 + 0.8 * openness).collect();
+        assert_eq!(gains, expected);
+        assert_eq!(gate.stage(), GateStage::Closed);
+    }
+
+    #[test]
+    fn burst_during_hold_and_release() {
+        let mut gate = test_gate(0.5, 0.5);
+
+        // Bursts of 10 samples with gaps of 5 and 15 samples. The first gap is shorter than the
+        // hold time, so the gate stays open. The second gap ends halfway through the release, so
+        // the gate attacks again from where it was.
+        let levels = (0..70).map(|sample_idx| match sample_idx {
+            0..=9 | 15..=24 | 40..=49 => 1.0,
+            _ => 0.0,
+        });
+        assert_eq!(stage_changes(&mut gate, levels), [
+            (0, GateStage::Attack),
+            (3, GateStage::Open),
+            (10, GateStage::Hold),
+            (15, GateStage::Open),
+            (25, GateStage::Hold),
+            (35, GateStage::Release),
+            (40, GateStage::Attack),
+            (41, GateStage::Open),
+            (50, GateStage::Hold),
+            (60, GateStage::Release),
+            (68, GateStage::Closed),
+        ]);
+    }
+
+    #[test]
+    fn hysteresis_prevents_retriggering() {
+        let mut gate = test_gate(0.5, 0.25);
+
+        // The level oscillates between the close and the open threshold after the gate opened,
+        // and again after it closed
+        let oscillating = |sample_idx: usize| [0.3, 0.45][sample_idx % 2];
+        let levels = (0..250).map(|sample_idx| match sample_idx {
+            0..=9 => 1.0,
+            10..=109 => oscillating(sample_idx),
+            110..=139 => 0.0,
+            _ => oscillating(sample_idx),
+        });
+        assert_eq!(stage_changes(&mut gate, levels), [
+            (0, GateStage::Attack),
+            (3, GateStage::Open),
+            (110, GateStage::Hold),
+            (120, GateStage::Release),
+            (128, GateStage::Closed),
+        ]);
+    }
+
+    #[test]
+    fn close_threshold_is_clamped() {
+        let mut gate = test_gate(0.5, 0.8);
+
+        // With the close threshold clamped to the open threshold, dropping just below the open
+        // threshold starts the hold stage
+        let levels = [1.0, 1.0, 1.0, 1.0, 0.6, 0.49];
+        assert_eq!(stage_changes(&mut gate, levels), [
+            (0, GateStage::Attack),
+            (3, GateStage::Open),
+            (5, GateStage::Hold),
+        ]);
+    }
+}
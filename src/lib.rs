@@ -1,22 +1,185 @@
+use nih_plug::buffer::ChannelSamples;
 use nih_plug::prelude::*;
 use nih_plug_egui::{ create_egui_editor, egui, widgets, EguiState };
-use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, RwLock };
 
-/// The time it takes for the peak meter to decay by 12 dB after switching to complete silence.
-const PEAK_METER_DECAY_MS: f64 = 150.0;
+use crate::auto_threshold::AutoThreshold;
+use crate::delay::DelayLine;
+use crate::detector::Detector;
+use crate::expander::{ Expander, MAX_RATIO };
+use crate::filter::KeyFilter;
+use crate::gate::{ ms_to_samples, EnvelopeFollower, Gate, GateStage, DETECTOR_RELEASE_MS };
+use crate::meter::{ PeakMeter, PEAK_HOLD_MS };
+use crate::multiband::{ Crossover, CrossoverAllpass, MultibandGate, MAX_BANDS };
+use crate::spectral::{ NoiseProfile, SpectralGate, MAX_FFT_SIZE };
+use crate::stereo::{ mid_side_decode_in_place, mid_side_encode_in_place };
+use crate::telemetry::{ ChannelFrame, Telemetry };
 
-/// This is mostly identical to the gain example, minus some fluff, and with a GUI.
-struct Noiseg8 {
+mod auto_threshold;
+mod delay;
+mod detector;
+mod editor;
+mod expander;
+mod filter;
+mod gate;
+mod meter;
+mod migration;
+mod multiband;
+mod presets;
+pub mod render;
+mod spectral;
+mod stereo;
+mod telemetry;
+#[cfg(test)]
+mod test_util;
+
+/// The default time it takes for the peak meters to decay by 12 dB after switching to complete
+/// silence.
+const PEAK_METER_DECAY_MS: f32 = 150.0;
+
+/// The longest RMS window the detector supports. The RMS buffers are allocated for this length in
+/// `initialize()`.
+const MAX_RMS_WINDOW_MS: f32 = 300.0;
+
+/// The maximum lookahead time. The delay lines are allocated for this length in `initialize()`.
+const MAX_LOOKAHEAD_MS: f32 = 10.0;
+
+/// The time it takes to crossfade between the processed and the dry signal when the plugin gets
+/// bypassed or un-bypassed.
+const BYPASS_FADE_MS: f32 = 10.0;
+
+/// The NoiseG8 noise gate. The gate, expander, and multiband modes share a per-sample signal
+/// chain, while the spectral mode replaces it with an STFT based [`SpectralGate`].
+pub struct Noiseg8 {
     params: Arc<Noiseg8Params>,
 
-    /// Needed to normalize the peak meter's response based on the sample rate.
-    peak_meter_decay_weight: f32,
-    /// The current data for the peak meter. This is stored as an [`Arc`] so we can share it between
-    /// the GUI and the audio processing parts. If you have more state to share, then it's a good
-    /// idea to put all of that in a struct behind a single `Arc`.
-    ///
-    /// This is stored as voltage gain.
-    peak_meter: Arc<AtomicF32>,
+    /// The output peak meters, one per main channel. Their state is published to `telemetry` at
+    /// the end of every block.
+    peak_meters: Vec<PeakMeter>,
+    /// Measures the output for the peak meters using the gate's detector mode, one per main
+    /// channel.
+    output_detectors: Vec<Detector>,
+    /// All data shared between the audio processing parts and the GUI, including the peak meters.
+    /// This is only written to while the editor is open.
+    telemetry: Arc<Telemetry>,
+    /// The telemetry for the block currently being processed, one frame per main channel. These
+    /// are accumulated sample by sample and pushed to `telemetry` at the end of the block.
+    telemetry_frames: Vec<ChannelFrame>,
+
+    /// The current sample rate, needed to convert the gate's timing parameters to samples.
+    sample_rate: f32,
+    /// The current key signal for every detector channel, after the key filter. Only holds the
+    /// current sample, this is scratch space so the key can also be routed to the output.
+    key_samples: Vec<f32>,
+    /// Band-limits the key signal before it reaches the envelope followers. One per detector
+    /// channel.
+    key_filters: Vec<KeyFilter>,
+    /// Turns the filtered key signal into a level using the selected detector mode. One per
+    /// detector channel.
+    detectors: Vec<Detector>,
+    /// One envelope follower per detector channel.
+    envelope_followers: Vec<EnvelopeFollower>,
+    /// The envelope followers' outputs for the current sample. Scratch space for combining the
+    /// detector channels according to the stereo link mode.
+    detector_levels: Vec<f32>,
+    /// One gate per main channel. When the channels are linked these all receive the same detector
+    /// level, so they stay in lockstep.
+    gates: Vec<Gate>,
+    /// One expander per main channel, used instead of the gates in expander mode.
+    expanders: Vec<Expander>,
+    /// Splits the filtered key signal into bands in multiband mode. One per detector channel.
+    key_crossovers: Vec<Crossover>,
+    /// One envelope follower per band for every detector channel.
+    band_envelope_followers: Vec<[EnvelopeFollower; MAX_BANDS]>,
+    /// The band envelope followers' outputs for the current sample, the multiband counterpart to
+    /// `detector_levels`.
+    band_detector_levels: Vec<[f32; MAX_BANDS]>,
+    /// One multiband gate per main channel, used instead of the gates in multiband mode.
+    multiband_gates: Vec<MultibandGate>,
+    /// Delays the audio path relative to the detector by the lookahead time. One per main channel.
+    lookahead_delay_lines: Vec<DelayLine>,
+    /// Delays the dry signal by the plugin's latency so it lines up with the gated signal when the
+    /// two are mixed. One per main channel.
+    dry_delay_lines: Vec<DelayLine>,
+    /// The delayed dry signal for the current block, one vector of `max_buffer_size` samples per
+    /// main channel.
+    dry_samples: Vec<Vec<f32>>,
+    /// Gives the dry signal the multiband crossovers' phase response before it's mixed with the
+    /// gated signal in multiband mode. Bypassing still uses the unaltered dry signal. One per main
+    /// channel.
+    dry_allpasses: Vec<CrossoverAllpass>,
+    /// How far the bypass crossfade has progressed, where 0 is fully processed and 1 is fully
+    /// bypassed.
+    bypass_fade: f32,
+    /// How much `bypass_fade` changes every sample during a crossfade.
+    bypass_fade_step: f32,
+    /// Gates the individual frequency bins in spectral mode. This replaces the entire per-sample
+    /// signal chain while active.
+    spectral_gate: SpectralGate,
+    /// Set when the spectral gate learned a new noise profile that still needs to be copied to
+    /// [`Noiseg8Params::noise_profile`].
+    noise_profile_changed: bool,
+    /// Set by the editor when loading a preset replaced [`Noiseg8Params::noise_profile`], so the
+    /// spectral gate needs to pick up the new profile.
+    noise_profile_loaded: Arc<AtomicBool>,
+    /// Measures the detector level while the auto threshold is armed.
+    auto_threshold: AutoThreshold,
+    /// Whether the auto threshold was armed during the last block. The threshold is proposed when
+    /// it gets disarmed.
+    auto_threshold_armed: bool,
+    /// The latency currently reported to the host, in samples. Used to only report changes.
+    latency_samples: u32,
+
+    /// The MIDI notes currently held that pass the note filter. In MIDI trigger mode the gate is
+    /// open while any of these are held.
+    midi_held_notes: [bool; 128],
+    /// The velocity of the last note that opened the gate in MIDI trigger mode, in `[0, 1]`.
+    midi_velocity: f32,
+    /// The channel and note number of the NoteOn event sent when the gate last opened, if the
+    /// matching NoteOff has not been sent yet. This is stored so the NoteOff always matches the
+    /// NoteOn, even if the MIDI output parameters change in between.
+    midi_output_note: Option<(u8, u8)>,
+    /// The NoteOn event for the last hit while its velocity is still being measured, as the number
+    /// of samples left in the measurement window and the highest detector level so far.
+    midi_output_pending_note: Option<(u32, f32)>,
+}
+
+/// The parameter values used by the per-sample processing in the gate, expander, and multiband
+/// modes. With sample accurate automation the buffer is split at every parameter change, so these
+/// are gathered once per block.
+struct BlockSettings {
+    /// The lookahead time in samples.
+    lookahead_samples: usize,
+    /// The plugin's latency in samples. The dry signal is delayed by this much.
+    dry_delay_samples: usize,
+    bypass: bool,
+
+    gate_mode: GateMode,
+    /// Whether `gate_mode` is [`GateMode::Multiband`].
+    multiband: bool,
+    duck: bool,
+    threshold_db: f32,
+    /// The range as a voltage gain.
+    floor: f32,
+    key_listen: bool,
+    detector_mode: DetectorMode,
+    stereo_link: StereoLink,
+    /// Whether the channels are gated as mid/side instead of left/right.
+    mid_side: bool,
+    mid_side_gating: MidSideGating,
+    /// Whether the detector level is fed to the auto threshold.
+    auto_threshold_armed: bool,
+
+    /// Whether the gate is triggered by MIDI notes instead of the detector.
+    midi_trigger: bool,
+    trigger_velocity: f32,
+    trigger_note_filter: bool,
+    trigger_note: u8,
+    midi_output: bool,
+    /// The length of the MIDI output's velocity measurement window in samples. This is the
+    /// lookahead time, so the NoteOn lines up with the hit at the output.
+    midi_velocity_window_samples: u32,
 }
 
 #[derive(Params)]
@@ -25,13 +188,522 @@ struct Noiseg8Params {
     /// restored.
     #[persist = "editor-state"]
     editor_state: Arc<EguiState>,
+    /// The noise profile learned by the spectral gate, saved together with the parameter state.
+    /// This is only written to from the audio thread when learning finishes.
+    #[persist = "noise-profile"]
+    noise_profile: Arc<RwLock<NoiseProfile>>,
+    /// The schema version of the saved state. Older states are upgraded in
+    /// [`Plugin::filter_state()`] before they're restored, so this is always the current version.
+    #[persist = "schema-version"]
+    schema_version: RwLock<u32>,
+
+    /// Crossfades to the dry signal. This is the plugin's bypass parameter, so hosts use it for
+    /// their own bypass button. The signal is still processed while bypassed so un-bypassing
+    /// doesn't start from a stale state.
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 
+    /// The gain applied to the input before the detector and the gate. This keeps the `gain` ID
+    /// from when it was the plugin's only gain parameter.
     #[id = "gain"]
-    pub gain: FloatParam,
+    pub input_gain: FloatParam,
+    /// The ratio between the gated and the dry signal, where 1 is fully gated. The dry signal is
+    /// the input before the input gain, delayed to match the plugin's latency. In multiband mode
+    /// it gradually takes on the crossovers' phase response as the mix goes up so the two don't
+    /// comb filter, while a mix of 0 still returns the dry signal exactly.
+    #[id = "mix"]
+    pub mix: FloatParam,
+    /// The gain applied to the output after the dry and gated signals have been mixed.
+    #[id = "output_gain"]
+    pub output_gain: FloatParam,
+
+    /// The time it takes for the output peak meters to decay by 12 dB, in milliseconds. This only
+    /// affects the editor.
+    #[id = "meter_decay"]
+    pub meter_decay: FloatParam,
+
+    /// Whether the gate is keyed from the main input or from the sidechain input.
+    #[id = "key_source"]
+    pub key_source: EnumParam<KeySource>,
+
+    /// Enables the high-pass filter on the detector signal.
+    #[id = "key_hpf"]
+    pub key_hpf_enabled: BoolParam,
+    #[id = "key_hpf_freq"]
+    pub key_hpf_frequency: FloatParam,
+    /// Enables the low-pass filter on the detector signal.
+    #[id = "key_lpf"]
+    pub key_lpf_enabled: BoolParam,
+    #[id = "key_lpf_freq"]
+    pub key_lpf_frequency: FloatParam,
+    /// The slope used for both key filters.
+    #[id = "key_slope"]
+    pub key_filter_slope: EnumParam<FilterSlope>,
+    /// Routes the filtered key signal to the output instead of the gated audio so the key filter
+    /// can be tuned by ear.
+    #[id = "key_listen"]
+    pub key_listen: BoolParam,
+
+    /// How the key signal's level is measured. The output meters use the same mode.
+    #[id = "detector_mode"]
+    pub detector_mode: EnumParam<DetectorMode>,
+    /// The window length for the RMS detector, in milliseconds.
+    #[id = "rms_window"]
+    pub rms_window: FloatParam,
+
+    /// Whether a stereo signal is gated as left/right or as mid/side.
+    #[id = "channel_mode"]
+    pub channel_mode: EnumParam<ChannelMode>,
+    /// Which of the mid and side components get gated in mid/side mode. The other component is
+    /// passed through untouched.
+    #[id = "ms_gating"]
+    pub mid_side_gating: EnumParam<MidSideGating>,
+    /// How the detector channels are combined to drive the gates. In mid/side mode the detector
+    /// channels are the key signal's mid and side components, so this also decides which component
+    /// keys which gate.
+    #[id = "stereo_link"]
+    pub stereo_link: EnumParam<StereoLink>,
+
+    /// Whether the gate is opened by the detector or by incoming MIDI notes.
+    #[id = "trigger"]
+    pub trigger_source: EnumParam<TriggerSource>,
+    /// How much a MIDI note's velocity scales the gain when the gate is open, from 0 (velocity is
+    /// ignored) to 1 (velocity maps directly to gain).
+    #[id = "trigger_vel"]
+    pub trigger_velocity: FloatParam,
+    /// Only respond to [`trigger_note`][Self::trigger_note] in MIDI trigger mode.
+    #[id = "trigger_filter"]
+    pub trigger_note_filter: BoolParam,
+    /// The MIDI note that opens the gate when the note filter is enabled.
+    #[id = "trigger_note"]
+    pub trigger_note: IntParam,
+
+    /// Send a NoteOn event for every hit that opens the gate or re-triggers it during its release,
+    /// and a NoteOff event when it closes. The NoteOn is sent on the sample the gate opens, with a
+    /// velocity that follows the detector level. With lookahead it's delayed until the hit reaches
+    /// the output instead, and the velocity follows the hit's peak within the lookahead time.
+    #[id = "midi_out"]
+    pub midi_output: BoolParam,
+    /// The note number for the MIDI output events.
+    #[id = "midi_out_note"]
+    pub midi_output_note: IntParam,
+    /// The MIDI channel for the MIDI output events, from 1 to 16.
+    #[id = "midi_out_channel"]
+    pub midi_output_channel: IntParam,
+
+    /// While enabled the detector level is measured, and disabling it sets the threshold to the
+    /// margin above the measured noise floor. The threshold is changed from the editor so the host
+    /// records it like any other parameter change, so this only works while the editor is open.
+    /// The editor has its own toggle for this and shows the measurement while it runs. Disabling
+    /// it while the editor is closed discards the measurement. Spectral mode doesn't
+    /// use the detector, so nothing is measured there. Learn a noise profile instead.
+    #[id = "auto_threshold"]
+    pub auto_threshold: BoolParam,
+    /// How far above the measured noise floor the auto threshold places the threshold, in
+    /// decibels.
+    #[id = "auto_threshold_margin"]
+    pub auto_threshold_margin: FloatParam,
+
+    /// Switches between a hard gate and a downward expander.
+    #[id = "mode"]
+    pub mode: EnumParam<GateMode>,
+    /// Inverts the gain reduction, so the signal is attenuated by the range while the key is above
+    /// the threshold. Combined with the sidechain input this ducks the main input under the key.
+    #[id = "duck"]
+    pub duck: BoolParam,
+    /// The detector level the gate opens at, in decibels.
+    #[id = "threshold"]
+    pub threshold: FloatParam,
+    /// How far below the threshold the detector level needs to drop before the gate starts closing,
+    /// in decibels.
+    #[id = "hysteresis"]
+    pub hysteresis: FloatParam,
+    /// How far the detector runs ahead of the audio path, in milliseconds. This is reported to the
+    /// host as latency.
+    #[id = "lookahead"]
+    pub lookahead: FloatParam,
+    /// The time it takes for the gate to fully open, in milliseconds.
+    #[id = "attack"]
+    pub attack: FloatParam,
+    /// How long the gate stays open after the detector level drops below the threshold, in
+    /// milliseconds.
+    #[id = "hold"]
+    pub hold: FloatParam,
+    /// The time it takes for the gate to fully close, in milliseconds.
+    #[id = "release"]
+    pub release: FloatParam,
+    /// Use [`hold_note`][Self::hold_note] and [`release_note`][Self::release_note] at the host's
+    /// tempo instead of the hold and release times in milliseconds. The millisecond times are still
+    /// used when the host doesn't report a tempo.
+    #[id = "tempo_sync"]
+    pub tempo_sync: BoolParam,
+    /// The hold time as a note value when tempo sync is enabled.
+    #[id = "hold_note"]
+    pub hold_note: EnumParam<NoteDivision>,
+    /// The release time as a note value when tempo sync is enabled. The bands' own release times
+    /// in multiband mode are not synced.
+    #[id = "release_note"]
+    pub release_note: EnumParam<NoteDivision>,
+    /// The attenuation applied while the gate is closed, in decibels.
+    #[id = "range"]
+    pub range: FloatParam,
+    /// The expander's ratio. Every decibel the detector level drops below the threshold lowers the
+    /// output by this many decibels, up to the range. [`MAX_RATIO`] means an infinite ratio.
+    #[id = "ratio"]
+    pub ratio: FloatParam,
+    /// The width of the expander's soft knee around the threshold, in decibels.
+    #[id = "knee"]
+    pub knee: FloatParam,
+
+    /// The spectral gate's FFT size. Larger sizes give a finer frequency resolution at the cost of
+    /// more latency and smeared transients.
+    #[id = "spectral_fft"]
+    pub spectral_fft_size: EnumParam<FftSize>,
+    /// While enabled, the spectral gate passes the audio through unchanged and measures its
+    /// spectrum. Disabling it replaces the noise profile with the measured average.
+    #[id = "spectral_learn"]
+    pub spectral_learn: BoolParam,
+    /// How far a bin needs to rise above the noise profile to pass the spectral gate, in decibels.
+    /// Without a noise profile the regular threshold is used for every bin instead.
+    #[id = "spectral_threshold"]
+    pub spectral_threshold: FloatParam,
+    /// The attenuation applied to bins below their threshold, in decibels.
+    #[id = "spectral_reduction"]
+    pub spectral_reduction: FloatParam,
+    /// The time constant for smoothing the per-bin gains over time, in milliseconds.
+    #[id = "spectral_time_smooth"]
+    pub spectral_time_smoothing: FloatParam,
+    /// The number of neighbouring bins on either side that get averaged into every bin's gain.
+    #[id = "spectral_freq_smooth"]
+    pub spectral_frequency_smoothing: IntParam,
+
+    /// The number of bands the signal is split into in multiband mode.
+    #[id = "num_bands"]
+    pub num_bands: IntParam,
+    /// The crossover frequency between the first and the second band.
+    #[id = "xover_1"]
+    pub crossover_1: FloatParam,
+    /// The crossover frequency between the second and the third band. Clamped so it's never below
+    /// the first crossover.
+    #[id = "xover_2"]
+    pub crossover_2: FloatParam,
+    /// The crossover frequency between the third and the fourth band. Clamped so it's never below
+    /// the second crossover.
+    #[id = "xover_3"]
+    pub crossover_3: FloatParam,
+    /// The gate settings for every band in multiband mode, from the lowest to the highest band.
+    #[nested(array, group = "Band")]
+    pub bands: [BandParams; MAX_BANDS],
+}
+
+/// A single band's gate settings in multiband mode. The hysteresis and hold time are shared by all
+/// bands.
+#[derive(Params)]
+struct BandParams {
+    /// The band's detector level the band opens at, in decibels.
+    #[id = "threshold"]
+    pub threshold: FloatParam,
+    /// The time it takes for the band to fully open, in milliseconds.
+    #[id = "attack"]
+    pub attack: FloatParam,
+    /// The time it takes for the band to fully close, in milliseconds.
+    #[id = "release"]
+    pub release: FloatParam,
+    /// The attenuation applied to the band while it's closed, in decibels.
+    #[id = "range"]
+    pub range: FloatParam,
+}
+
+/// The signal that drives the gate's detector.
+#[derive(Enum, Debug, PartialEq)]
+enum KeySource {
+    /// Key the gate from the main input.
+    #[id = "internal"]
+    #[name = "Internal"]
+    Internal,
+    /// Key the gate from the auxiliary sidechain input. Falls back to the main input if the host
+    /// chose an audio layout without a sidechain input. Hosts pass silence to a sidechain input
+    /// that isn't connected to anything, so the gate then stays closed.
+    #[id = "external"]
+    #[name = "External"]
+    External,
+}
+
+/// The slope of the key filters. Each step adds another second order Butterworth section.
+#[derive(Enum, Debug, PartialEq)]
+enum FilterSlope {
+    #[id = "12"]
+    #[name = "12 dB/oct"]
+    Slope12,
+    #[id = "24"]
+    #[name = "24 dB/oct"]
+    Slope24,
+    #[id = "48"]
+    #[name = "48 dB/oct"]
+    Slope48,
+}
+
+/// How the detector computes the key signal's level.
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum DetectorMode {
+    /// The absolute sample value.
+    #[id = "peak"]
+    #[name = "Peak"]
+    Peak,
+    /// The root mean square over a sliding window.
+    #[id = "rms"]
+    #[name = "RMS"]
+    Rms,
+    /// The peak value of the signal oversampled by a factor four, which also catches inter-sample
+    /// peaks.
+    #[id = "true_peak"]
+    #[name = "True Peak"]
+    TruePeak,
+}
+
+/// What opens the gate.
+#[derive(Enum, Debug, PartialEq)]
+enum TriggerSource {
+    /// The gate is driven by the detector.
+    #[id = "audio"]
+    #[name = "Audio"]
+    Audio,
+    /// The gate opens on NoteOn events and starts releasing on NoteOff events. The hold time is
+    /// not used.
+    #[id = "midi"]
+    #[name = "MIDI"]
+    Midi,
+}
+
+/// The kind of gain reduction applied below the threshold.
+#[derive(Enum, Debug, PartialEq)]
+enum GateMode {
+    /// Jump between unity gain and the range floor using the attack, hold, and release times.
+    #[id = "gate"]
+    #[name = "Gate"]
+    Gate,
+    /// Attenuate according to the ratio and knee, see [`expander::expander_gain_db()`].
+    #[id = "expander"]
+    #[name = "Expander"]
+    Expander,
+    /// Split the signal into bands and gate every band on its own, using the band's own
+    /// threshold, attack, release, and range. The bands are detected from the filtered key signal
+    /// split at the same crossover frequencies, and the MIDI trigger's velocity scaling does not
+    /// apply.
+    #[id = "multiband"]
+    #[name = "Multiband"]
+    Multiband,
+    /// Gate every frequency bin on its own against the learned noise profile. The key source, key
+    /// filters, detector, stereo link, MIDI trigger, and the gate's timing parameters are not used
+    /// in this mode.
+    #[id = "spectral"]
+    #[name = "Spectral"]
+    Spectral,
+}
+
+/// The spectral gate's FFT size.
+#[derive(Enum, Debug, PartialEq)]
+enum FftSize {
+    #[id = "512"]
+    #[name = "512"]
+    Size512,
+    #[id = "1024"]
+    #[name = "1024"]
+    Size1024,
+    #[id = "2048"]
+    #[name = "2048"]
+    Size2048,
+    #[id = "4096"]
+    #[name = "4096"]
+    Size4096,
+}
 
-    // TODO: Remove this parameter when we're done implementing the widgets
-    #[id = "foobar"]
-    pub some_int: IntParam,
+/// The representation a stereo signal is gated in.
+#[derive(Enum, Debug, PartialEq)]
+enum ChannelMode {
+    #[id = "lr"]
+    #[name = "Left/Right"]
+    LeftRight,
+    /// Both the audio and the key signal are encoded to mid/side before the gate, and the audio is
+    /// decoded back to left/right afterwards.
+    #[id = "ms"]
+    #[name = "Mid/Side"]
+    MidSide,
+}
+
+/// The components gated in mid/side mode.
+#[derive(Enum, Debug, PartialEq)]
+enum MidSideGating {
+    #[id = "both"]
+    #[name = "Mid and Side"]
+    Both,
+    #[id = "mid"]
+    #[name = "Mid Only"]
+    Mid,
+    #[id = "side"]
+    #[name = "Side Only"]
+    Side,
+}
+
+/// How the detector levels of the individual channels drive the gates.
+#[derive(Enum, Debug, PartialEq)]
+enum StereoLink {
+    /// All channels are gated by the loudest detector channel.
+    #[id = "max"]
+    #[name = "Linked (Max)"]
+    Max,
+    /// All channels are gated by the average of the detector channels.
+    #[id = "average"]
+    #[name = "Linked (Average)"]
+    Average,
+    /// Every channel is gated by its own detector channel.
+    #[id = "independent"]
+    #[name = "Independent"]
+    Independent,
+}
+
+/// A note value for the tempo synced hold and release times. Dotted notes are one and a half
+/// times as long as the plain note, and triplets last two thirds of the plain note.
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum NoteDivision {
+    #[id = "1/1"]
+    #[name = "1/1"]
+    Whole,
+    #[id = "1/2d"]
+    #[name = "1/2D"]
+    HalfDotted,
+    #[id = "1/2"]
+    #[name = "1/2"]
+    Half,
+    #[id = "1/2t"]
+    #[name = "1/2T"]
+    HalfTriplet,
+    #[id = "1/4d"]
+    #[name = "1/4D"]
+    QuarterDotted,
+    #[id = "1/4"]
+    #[name = "1/4"]
+    Quarter,
+    #[id = "1/4t"]
+    #[name = "1/4T"]
+    QuarterTriplet,
+    #[id = "1/8d"]
+    #[name = "1/8D"]
+    EighthDotted,
+    #[id = "1/8"]
+    #[name = "1/8"]
+    Eighth,
+    #[id = "1/8t"]
+    #[name = "1/8T"]
+    EighthTriplet,
+    #[id = "1/16d"]
+    #[name = "1/16D"]
+    SixteenthDotted,
+    #[id = "1/16"]
+    #[name = "1/16"]
+    Sixteenth,
+    #[id = "1/16t"]
+    #[name = "1/16T"]
+    SixteenthTriplet,
+    #[id = "1/32"]
+    #[name = "1/32"]
+    ThirtySecond,
+}
+
+impl NoteDivision {
+    /// The note's length in quarter notes.
+    fn beats(&self) -> f64 {
+        match self {
+            NoteDivision::Whole => 4.0,
+            NoteDivision::HalfDotted => 3.0,
+            NoteDivision::Half => 2.0,
+            NoteDivision::HalfTriplet => 4.0 / 3.0,
+            NoteDivision::QuarterDotted => 1.5,
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::QuarterTriplet => 2.0 / 3.0,
+            NoteDivision::EighthDotted => 0.75,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::EighthTriplet => 1.0 / 3.0,
+            NoteDivision::SixteenthDotted => 0.375,
+            NoteDivision::Sixteenth => 0.25,
+            NoteDivision::SixteenthTriplet => 1.0 / 6.0,
+            NoteDivision::ThirtySecond => 0.125,
+        }
+    }
+
+    /// The note's length in milliseconds at `tempo` quarter notes per minute.
+    fn duration_ms(&self, tempo: f64) -> f32 {
+        (self.beats() * 60_000.0 / tempo) as f32
+    }
+}
+
+impl FilterSlope {
+    /// The number of cascaded biquads needed for this slope.
+    fn num_stages(&self) -> usize {
+        match self {
+            FilterSlope::Slope12 => 1,
+            FilterSlope::Slope24 => 2,
+            FilterSlope::Slope48 => 4,
+        }
+    }
+}
+
+impl BandParams {
+    /// The parameters for the band at `band_idx`, counting from zero.
+    fn new(band_idx: usize) -> Self {
+        let band_number = band_idx + 1;
+
+        Self {
+            threshold: FloatParam::new(
+                format!("Band {band_number} Threshold"),
+                -40.0,
+                FloatRange::Linear { min: -80.0, max: 0.0 }
+            )
+                .with_unit(" dB")
+                .with_step_size(0.1),
+            attack: FloatParam::new(
+                format!("Band {band_number} Attack"),
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 100.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                }
+            )
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            release: FloatParam::new(
+                format!("Band {band_number} Release"),
+                100.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                }
+            )
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            range: FloatParam::new(
+                format!("Band {band_number} Range"),
+                -80.0,
+                FloatRange::Linear { min: util::MINUS_INFINITY_DB, max: 0.0 }
+            )
+                .with_unit(" dB")
+                .with_step_size(0.1),
+        }
+    }
+}
+
+impl FftSize {
+    /// The FFT size in samples.
+    fn size(&self) -> usize {
+        match self {
+            FftSize::Size512 => 512,
+            FftSize::Size1024 => 1024,
+            FftSize::Size2048 => 2048,
+            FftSize::Size4096 => 4096,
+        }
+    }
 }
 
 impl Default for Noiseg8 {
@@ -39,8 +711,40 @@ impl Default for Noiseg8 {
         Self {
             params: Arc::new(Noiseg8Params::default()),
 
-            peak_meter_decay_weight: 1.0,
-            peak_meter: Arc::new(AtomicF32::new(util::MINUS_INFINITY_DB)),
+            peak_meters: Vec::new(),
+            output_detectors: Vec::new(),
+            telemetry: Arc::new(Telemetry::default()),
+            telemetry_frames: Vec::new(),
+
+            sample_rate: 1.0,
+            key_samples: Vec::new(),
+            key_filters: Vec::new(),
+            detectors: Vec::new(),
+            envelope_followers: Vec::new(),
+            detector_levels: Vec::new(),
+            gates: Vec::new(),
+            expanders: Vec::new(),
+            key_crossovers: Vec::new(),
+            band_envelope_followers: Vec::new(),
+            band_detector_levels: Vec::new(),
+            multiband_gates: Vec::new(),
+            lookahead_delay_lines: Vec::new(),
+            dry_delay_lines: Vec::new(),
+            dry_samples: Vec::new(),
+            dry_allpasses: Vec::new(),
+            bypass_fade: 0.0,
+            bypass_fade_step: 1.0,
+            spectral_gate: SpectralGate::new(0),
+            noise_profile_changed: false,
+            noise_profile_loaded: Arc::new(AtomicBool::new(false)),
+            auto_threshold: AutoThreshold::default(),
+            auto_threshold_armed: false,
+            latency_samples: 0,
+
+            midi_held_notes: [false; 128],
+            midi_velocity: 1.0,
+            midi_output_note: None,
+            midi_output_pending_note: None,
         }
     }
 }
@@ -49,9 +753,27 @@ impl Default for Noiseg8Params {
     fn default() -> Self {
         Self {
             editor_state: EguiState::from_size(800, 600),
+            noise_profile: Arc::new(RwLock::new(NoiseProfile::default())),
+            schema_version: RwLock::new(migration::SCHEMA_VERSION),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
 
             // See the main gain example for more details
-            gain: FloatParam::new("Gain", util::db_to_gain(0.0), FloatRange::Skewed {
+            input_gain: FloatParam::new("Input Gain", util::db_to_gain(0.0), FloatRange::Skewed {
+                min: util::db_to_gain(-30.0),
+                max: util::db_to_gain(30.0),
+                factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+            })
+                .with_smoother(SmoothingStyle::Logarithmic(50.0))
+                .with_unit(" dB")
+                .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+                .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            mix: FloatParam::new("Mix", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            output_gain: FloatParam::new("Output Gain", util::db_to_gain(0.0), FloatRange::Skewed {
                 min: util::db_to_gain(-30.0),
                 max: util::db_to_gain(30.0),
                 factor: FloatRange::gain_skew_factor(-30.0, 30.0),
@@ -60,7 +782,218 @@ impl Default for Noiseg8Params {
                 .with_unit(" dB")
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
-            some_int: IntParam::new("Something", 3, IntRange::Linear { min: 0, max: 3 }),
+
+            meter_decay: FloatParam::new("Meter Decay", PEAK_METER_DECAY_MS, FloatRange::Skewed {
+                min: 10.0,
+                max: 2000.0,
+                factor: FloatRange::skew_factor(-1.0),
+            })
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(0))
+                .non_automatable(),
+
+            key_source: EnumParam::new("Key Source", KeySource::Internal),
+
+            key_hpf_enabled: BoolParam::new("Key HPF", false),
+            key_hpf_frequency: FloatParam::new("Key HPF Frequency", 100.0, FloatRange::Skewed {
+                min: 20.0,
+                max: 5000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+                .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            key_lpf_enabled: BoolParam::new("Key LPF", false),
+            key_lpf_frequency: FloatParam::new("Key LPF Frequency", 10000.0, FloatRange::Skewed {
+                min: 200.0,
+                max: 20000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+                .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            key_filter_slope: EnumParam::new("Key Filter Slope", FilterSlope::Slope24),
+            key_listen: BoolParam::new("Key Listen", false),
+
+            detector_mode: EnumParam::new("Detector Mode", DetectorMode::Peak),
+            rms_window: FloatParam::new("RMS Window", 10.0, FloatRange::Skewed {
+                min: 1.0,
+                max: MAX_RMS_WINDOW_MS,
+                factor: FloatRange::skew_factor(-1.0),
+            })
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            channel_mode: EnumParam::new("Channel Mode", ChannelMode::LeftRight),
+            mid_side_gating: EnumParam::new("M/S Gating", MidSideGating::Both),
+            stereo_link: EnumParam::new("Stereo Link", StereoLink::Max),
+
+            trigger_source: EnumParam::new("Trigger", TriggerSource::Audio),
+            trigger_velocity: FloatParam::new("Trigger Velocity", 0.0, FloatRange::Linear {
+                min: 0.0,
+                max: 1.0,
+            })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            trigger_note_filter: BoolParam::new("Trigger Note Filter", false),
+            trigger_note: IntParam::new("Trigger Note", 36, IntRange::Linear { min: 0, max: 127 })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
+
+            midi_output: BoolParam::new("MIDI Output", false),
+            midi_output_note: IntParam::new("MIDI Output Note", 36, IntRange::Linear {
+                min: 0,
+                max: 127,
+            })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            midi_output_channel: IntParam::new("MIDI Output Channel", 1, IntRange::Linear {
+                min: 1,
+                max: 16,
+            }),
+
+            auto_threshold: BoolParam::new("Auto Threshold", false).non_automatable(),
+            auto_threshold_margin: FloatParam::new(
+                "Auto Threshold Margin",
+                6.0,
+                FloatRange::Linear { min: 0.0, max: 24.0 }
+            )
+                .with_unit(" dB")
+                .with_step_size(0.1),
+
+            mode: EnumParam::new("Mode", GateMode::Gate),
+            duck: BoolParam::new("Duck", false),
+            threshold: FloatParam::new("Threshold", -40.0, FloatRange::Linear {
+                min: -80.0,
+                max: 0.0,
+            })
+                .with_unit(" dB")
+                .with_step_size(0.1),
+            hysteresis: FloatParam::new("Hysteresis", 3.0, FloatRange::Linear {
+                min: 0.0,
+                max: 24.0,
+            })
+                .with_unit(" dB")
+                .with_step_size(0.1),
+            lookahead: FloatParam::new("Lookahead", 0.0, FloatRange::Linear {
+                min: 0.0,
+                max: MAX_LOOKAHEAD_MS,
+            })
+                .with_unit(" ms")
+                .with_step_size(0.01),
+            attack: FloatParam::new("Attack", 1.0, FloatRange::Skewed {
+                min: 0.01,
+                max: 100.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            hold: FloatParam::new("Hold", 50.0, FloatRange::Skewed {
+                min: 0.0,
+                max: 1000.0,
+                factor: FloatRange::skew_factor(-1.0),
+            })
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            release: FloatParam::new("Release", 100.0, FloatRange::Skewed {
+                min: 1.0,
+                max: 5000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            tempo_sync: BoolParam::new("Tempo Sync", false),
+            hold_note: EnumParam::new("Hold Note", NoteDivision::Sixteenth),
+            release_note: EnumParam::new("Release Note", NoteDivision::Eighth),
+            range: FloatParam::new("Range", -80.0, FloatRange::Linear {
+                min: util::MINUS_INFINITY_DB,
+                max: 0.0,
+            })
+                .with_unit(" dB")
+                .with_step_size(0.1),
+            ratio: FloatParam::new("Ratio", 2.0, FloatRange::Skewed {
+                min: 1.0,
+                max: MAX_RATIO,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_value_to_string(
+                    Arc::new(|value: f32| {
+                        if value >= MAX_RATIO {
+                            String::from("1:\u{221e}")
+                        } else {
+                            format!("1:{value:.1}")
+                        }
+                    })
+                )
+                .with_string_to_value(
+                    Arc::new(|string: &str| {
+                        let string = string.trim().trim_start_matches("1:");
+                        if string == "\u{221e}" || string.eq_ignore_ascii_case("inf") {
+                            Some(MAX_RATIO)
+                        } else {
+                            string.parse().ok()
+                        }
+                    })
+                ),
+            knee: FloatParam::new("Knee", 6.0, FloatRange::Linear {
+                min: 0.0,
+                max: 24.0,
+            })
+                .with_unit(" dB")
+                .with_step_size(0.1),
+
+            spectral_fft_size: EnumParam::new("FFT Size", FftSize::Size2048),
+            spectral_learn: BoolParam::new("Learn Noise", false).non_automatable(),
+            spectral_threshold: FloatParam::new("Spectral Threshold", 6.0, FloatRange::Linear {
+                min: 0.0,
+                max: 24.0,
+            })
+                .with_unit(" dB")
+                .with_step_size(0.1),
+            spectral_reduction: FloatParam::new("Spectral Reduction", -24.0, FloatRange::Linear {
+                min: util::MINUS_INFINITY_DB,
+                max: 0.0,
+            })
+                .with_unit(" dB")
+                .with_step_size(0.1),
+            spectral_time_smoothing: FloatParam::new("Time Smoothing", 50.0, FloatRange::Skewed {
+                min: 0.0,
+                max: 1000.0,
+                factor: FloatRange::skew_factor(-1.0),
+            })
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            spectral_frequency_smoothing: IntParam::new(
+                "Frequency Smoothing",
+                2,
+                IntRange::Linear { min: 0, max: 16 }
+            ).with_unit(" bins"),
+
+            num_bands: IntParam::new("Bands", 3, IntRange::Linear {
+                min: 2,
+                max: MAX_BANDS as i32,
+            }),
+            crossover_1: FloatParam::new("Crossover 1", 200.0, FloatRange::Skewed {
+                min: 20.0,
+                max: 20000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+                .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            crossover_2: FloatParam::new("Crossover 2", 2000.0, FloatRange::Skewed {
+                min: 20.0,
+                max: 20000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+                .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            crossover_3: FloatParam::new("Crossover 3", 8000.0, FloatRange::Skewed {
+                min: 20.0,
+                max: 20000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            })
+                .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+                .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            bands: std::array::from_fn(BandParams::new),
         }
     }
 }
@@ -76,6 +1009,26 @@ impl Plugin for Noiseg8 {
     // The first audio IO layout is used as the default. The other layouts may be selected either
     // explicitly or automatically by the host or the user depending on the plugin API/backend.
     const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
         AudioIOLayout {
             main_input_channels: NonZeroU32::new(2),
             main_output_channels: NonZeroU32::new(2),
@@ -88,6 +1041,8 @@ impl Plugin for Noiseg8 {
         },
     ];
 
+    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::Basic;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     // If the plugin can send or receive SysEx messages, it can define a type to wrap around those
@@ -103,17 +1058,39 @@ impl Plugin for Noiseg8 {
         self.params.clone()
     }
 
+    fn filter_state(state: &mut PluginState) {
+        migration::migrate(state);
+    }
+
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         let params = self.params.clone();
-        let peak_meter = self.peak_meter.clone();
+        let telemetry = self.telemetry.clone();
+        let noise_profile_loaded = self.noise_profile_loaded.clone();
         create_egui_editor(
             self.params.editor_state.clone(),
-            (),
+            editor::PresetBrowser::default(),
             |_, _| {},
-            move |egui_ctx, setter, _state| {
+            move |egui_ctx, setter, preset_browser| {
                 egui::Window::new("controls").show(egui_ctx, |ui| {
                     ui.set_width(300.0);
                     ui.label(format!("overlay please"));
+
+                    if editor::preset_browser(ui, preset_browser, &params, setter) {
+                        noise_profile_loaded.store(true, Ordering::Release);
+                    }
+
+                    editor::auto_threshold(ui, &params, setter, &telemetry);
+
+                    if let Ok(noise_profile) = params.noise_profile.try_read() {
+                        ui.label(if noise_profile.is_empty() {
+                            "Noise profile: none"
+                        } else {
+                            "Noise profile: learned"
+                        });
+                    }
+
+                    editor::peak_meters(ui, &telemetry);
+                    editor::telemetry_view(ui, &telemetry);
                 });
             }
         )
@@ -121,63 +1098,928 @@ impl Plugin for Noiseg8 {
 
     fn initialize(
         &mut self,
-        _audio_io_layout: &AudioIOLayout,
+        audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>
+        context: &mut impl InitContext<Self>
     ) -> bool {
-        // After `PEAK_METER_DECAY_MS` milliseconds of pure silence, the peak meter's value should
-        // have dropped by 12 dB
-        self.peak_meter_decay_weight = (0.25f64).powf(
-            (((buffer_config.sample_rate as f64) * PEAK_METER_DECAY_MS) / 1000.0).recip()
-        ) as f32;
+        self.sample_rate = buffer_config.sample_rate;
+
+        // The detector is fed from either the main input or the sidechain input, so there needs to
+        // be a detector channel for every channel of the wider of the two
+        let num_main_channels = audio_io_layout.main_input_channels
+            .map(NonZeroU32::get)
+            .unwrap_or(0);
+        let num_sidechain_channels = audio_io_layout.aux_input_ports
+            .first()
+            .map(|channels| channels.get())
+            .unwrap_or(0);
+        let num_detector_channels = num_main_channels.max(num_sidechain_channels) as usize;
+        let mut envelope_follower = EnvelopeFollower::default();
+        envelope_follower.set_release(self.sample_rate, DETECTOR_RELEASE_MS);
+        self.key_samples = vec![0.0; num_detector_channels];
+        self.key_filters = vec![KeyFilter::default(); num_detector_channels];
+        let max_rms_window = ms_to_samples(self.sample_rate, MAX_RMS_WINDOW_MS).ceil();
+        self.detectors = vec![Detector::new(max_rms_window as usize); num_detector_channels];
+        self.envelope_followers = vec![envelope_follower; num_detector_channels];
+        self.detector_levels = vec![0.0; num_detector_channels];
+        self.gates = vec![Gate::default(); num_main_channels as usize];
+        self.expanders = vec![Expander::default(); num_main_channels as usize];
+        self.key_crossovers = vec![Crossover::default(); num_detector_channels];
+        self.band_envelope_followers = vec![[envelope_follower; MAX_BANDS]; num_detector_channels];
+        self.band_detector_levels = vec![[0.0; MAX_BANDS]; num_detector_channels];
+        self.multiband_gates = vec![MultibandGate::default(); num_main_channels as usize];
+        self.dry_allpasses = vec![CrossoverAllpass::default(); num_main_channels as usize];
+
+        self.telemetry.set_num_channels(num_main_channels as usize);
+        self.telemetry_frames = vec![ChannelFrame::default(); num_main_channels as usize];
+
+        let max_lookahead_samples = ms_to_samples(self.sample_rate, MAX_LOOKAHEAD_MS).ceil();
+        self.lookahead_delay_lines = vec![
+            DelayLine::new(max_lookahead_samples as usize);
+            num_main_channels as usize
+        ];
+        self.spectral_gate = SpectralGate::new(num_main_channels as usize);
+        self.spectral_gate.set_fft_size(self.params.spectral_fft_size.value().size());
+        // The noise profile may have been restored together with the rest of the plugin's state
+        if let Ok(noise_profile) = self.params.noise_profile.read() {
+            self.spectral_gate.set_noise_profile(&noise_profile);
+        }
+        self.noise_profile_changed = false;
+
+        self.latency_samples = self.current_latency_samples();
+        context.set_latency_samples(self.latency_samples);
+
+        // The dry signal needs to be delayed by whichever mode has the longest latency
+        let max_latency_samples = (max_lookahead_samples as usize).max(MAX_FFT_SIZE);
+        self.dry_delay_lines = vec![
+            DelayLine::new(max_latency_samples);
+            num_main_channels as usize
+        ];
+        self.dry_samples = vec![
+            vec![0.0; buffer_config.max_buffer_size as usize];
+            num_main_channels as usize
+        ];
+        self.bypass_fade_step = ms_to_samples(self.sample_rate, BYPASS_FADE_MS).max(1.0).recip();
+
+        self.peak_meters = vec![PeakMeter::default(); num_main_channels as usize];
+        self.output_detectors = vec![
+            Detector::new(max_rms_window as usize);
+            num_main_channels as usize
+        ];
 
         true
     }
 
+    fn reset(&mut self) {
+        for peak_meter in &mut self.peak_meters {
+            peak_meter.reset();
+        }
+        for delay_line in self.lookahead_delay_lines.iter_mut().chain(&mut self.dry_delay_lines) {
+            delay_line.reset();
+        }
+        for key_filter in &mut self.key_filters {
+            key_filter.reset();
+        }
+        for detector in self.detectors.iter_mut().chain(&mut self.output_detectors) {
+            detector.reset();
+        }
+        for envelope_follower in &mut self.envelope_followers {
+            envelope_follower.reset();
+        }
+        for gate in &mut self.gates {
+            gate.reset();
+        }
+        self.midi_held_notes = [false; 128];
+        self.midi_output_note = None;
+        self.midi_output_pending_note = None;
+        for expander in &mut self.expanders {
+            expander.reset();
+        }
+        for key_crossover in &mut self.key_crossovers {
+            key_crossover.reset();
+        }
+        for envelope_follower in self.band_envelope_followers.iter_mut().flatten() {
+            envelope_follower.reset();
+        }
+        for multiband_gate in &mut self.multiband_gates {
+            multiband_gate.reset();
+        }
+        for dry_allpass in &mut self.dry_allpasses {
+            dry_allpass.reset();
+        }
+        self.spectral_gate.reset();
+        self.auto_threshold.reset();
+        // A reset is not audible, so there's no need to fade in or out of the bypass
+        self.bypass_fade = if self.params.bypass.value() { 1.0 } else { 0.0 };
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>
     ) -> ProcessStatus {
-        for channel_samples in buffer.iter_samples() {
-            let mut amplitude = 0.0;
-            let num_samples = channel_samples.len();
-
-            let gain = self.params.gain.smoothed.next();
-            for sample in channel_samples {
-                *sample *= gain;
-                amplitude += *sample;
-            }
-
-            // To save resources, a plugin can (and probably should!) only perform expensive
-            // calculations that are only displayed on the GUI while the GUI is open
-            if self.params.editor_state.is_open() {
-                amplitude = (amplitude / (num_samples as f32)).abs();
-                let current_peak_meter = self.peak_meter.load(std::sync::atomic::Ordering::Relaxed);
-                let new_peak_meter = if amplitude > current_peak_meter {
-                    amplitude
-                } else {
-                    current_peak_meter * self.peak_meter_decay_weight +
-                        amplitude * (1.0 - self.peak_meter_decay_weight)
-                };
+        self.spectral_gate.set_fft_size(self.params.spectral_fft_size.value().size());
+        let latency_samples = self.current_latency_samples();
+        if latency_samples != self.latency_samples {
+            self.latency_samples = latency_samples;
+            context.set_latency_samples(self.latency_samples);
+        }
 
-                self.peak_meter.store(new_peak_meter, std::sync::atomic::Ordering::Relaxed);
+        self.update_noise_profile();
+        let auto_threshold_armed = self.update_auto_threshold();
+
+        // The output detectors feed the peak meters, so they're configured even in spectral mode
+        let rms_window = ms_to_samples(self.sample_rate, self.params.rms_window.value()).round();
+        for detector in self.detectors.iter_mut().chain(&mut self.output_detectors) {
+            detector.set_rms_window(rms_window as usize);
+        }
+
+        // To save resources, a plugin can (and probably should!) only perform expensive
+        // calculations that are only displayed on the GUI while the GUI is open
+        let telemetry_enabled = self.params.editor_state.is_open();
+        if telemetry_enabled {
+            self.telemetry_frames.fill(ChannelFrame::default());
+
+            let meter_decay = self.params.meter_decay.value();
+            for peak_meter in &mut self.peak_meters {
+                peak_meter.set_timing(self.sample_rate, meter_decay, PEAK_HOLD_MS);
+            }
+        }
+
+        if self.params.mode.value() == GateMode::Spectral {
+            self.process_spectral(buffer, context, telemetry_enabled);
+            if telemetry_enabled {
+                self.publish_telemetry();
+            }
+
+            return ProcessStatus::Normal;
+        }
+
+        let block = self.configure_block(context.transport().tempo, auto_threshold_armed);
+        let sidechain = match self.params.key_source.value() {
+            KeySource::Internal => None,
+            KeySource::External => aux.inputs.first().map(|buffer| buffer.as_slice_immutable()),
+        };
+
+        let mut next_event = context.next_event();
+        for (sample_idx, mut channel_samples) in buffer.iter_samples().enumerate() {
+            self.handle_midi_events(context, &mut next_event, sample_idx, &block);
+
+            self.process_input(
+                &mut channel_samples,
+                sample_idx,
+                block.dry_delay_samples,
+                telemetry_enabled
+            );
+            if block.mid_side {
+                mid_side_encode_in_place(channel_samples.iter_mut());
+            }
+
+            let (linked_detector_level, linked_band_levels) = self.compute_detector_levels(
+                &mut channel_samples,
+                sidechain,
+                sample_idx,
+                &block
+            );
+            let (gate_triggered, open_detector_level) = self.apply_gates(
+                &mut channel_samples,
+                linked_detector_level,
+                &linked_band_levels,
+                &block,
+                telemetry_enabled
+            );
+            self.send_midi_output(
+                context,
+                sample_idx,
+                gate_triggered,
+                open_detector_level,
+                &block
+            );
+
+            if block.mid_side {
+                mid_side_decode_in_place(channel_samples.iter_mut());
             }
+            self.process_output(
+                &mut channel_samples,
+                sample_idx,
+                block.bypass,
+                block.multiband,
+                block.detector_mode,
+                telemetry_enabled
+            );
+        }
+
+        if telemetry_enabled {
+            self.publish_telemetry();
         }
 
         ProcessStatus::Normal
     }
 }
 
+impl Noiseg8 {
+    /// The current lookahead time in samples.
+    fn lookahead_samples(&self) -> usize {
+        ms_to_samples(self.sample_rate, self.params.lookahead.value()).round() as usize
+    }
+
+    /// The latency for the current mode in samples. This is the STFT's latency in spectral mode,
+    /// and the lookahead time otherwise.
+    fn current_latency_samples(&self) -> u32 {
+        match self.params.mode.value() {
+            GateMode::Spectral => self.spectral_gate.latency_samples(),
+            GateMode::Gate | GateMode::Expander | GateMode::Multiband => {
+                self.lookahead_samples() as u32
+            }
+        }
+    }
+
+    /// Start and stop learning the spectral gate's noise profile, and keep the profile in sync
+    /// with the persisted [`Noiseg8Params::noise_profile`].
+    fn update_noise_profile(&mut self) {
+        // Learning is started and stopped from the parameter so it can be toggled from the editor,
+        // and the result is stored in the persisted noise profile so it's saved with the project
+        let spectral_learn = self.params.spectral_learn.value();
+        if spectral_learn && !self.spectral_gate.is_learning() {
+            self.spectral_gate.start_learning();
+        } else if !spectral_learn && self.spectral_gate.is_learning() {
+            self.noise_profile_changed |= self.spectral_gate.finish_learning();
+        }
+        if self.noise_profile_changed {
+            // The editor may be reading the profile, in which case this is retried on the next
+            // block
+            if let Ok(mut noise_profile) = self.params.noise_profile.try_write() {
+                noise_profile.copy_from(self.spectral_gate.noise_profile());
+                self.noise_profile_changed = false;
+            }
+        }
+        if self.noise_profile_loaded.swap(false, Ordering::Acquire) {
+            match self.params.noise_profile.try_read() {
+                Ok(noise_profile) => self.spectral_gate.set_noise_profile(&noise_profile),
+                // The editor may still be writing the profile
+                Err(_) => self.noise_profile_loaded.store(true, Ordering::Release),
+            }
+        }
+    }
+
+    /// Start measuring when the auto threshold gets armed, and propose a threshold when it gets
+    /// disarmed. Returns whether the auto threshold is armed.
+    fn update_auto_threshold(&mut self) -> bool {
+        // Only the editor can apply the proposed threshold, so while it's closed there's no point
+        // in proposing one. An earlier proposal is dropped so it isn't applied out of the blue
+        // when the editor gets opened again.
+        let editor_open = self.params.editor_state.is_open();
+        if !editor_open {
+            self.telemetry.clear_proposed_threshold();
+        }
+
+        let auto_threshold_armed = self.params.auto_threshold.value();
+        let margin_db = self.params.auto_threshold_margin.value();
+        if auto_threshold_armed && !self.auto_threshold_armed {
+            self.auto_threshold.reset();
+        } else if !auto_threshold_armed && self.auto_threshold_armed && editor_open {
+            if let Some(threshold_db) = self.auto_threshold.propose(margin_db) {
+                self.telemetry.propose_threshold(threshold_db);
+            }
+        }
+        self.auto_threshold_armed = auto_threshold_armed;
+
+        // The editor shows the measurement while it's running
+        let measurement = self.auto_threshold
+            .noise_floor_db()
+            .zip(self.auto_threshold.propose(margin_db))
+            .filter(|_| auto_threshold_armed);
+        self.telemetry.set_auto_threshold_measurement(measurement);
+
+        auto_threshold_armed
+    }
+
+    /// Configure the gates, expanders, multiband gates, and key filters for the current block, and
+    /// gather the parameter values the per-sample processing needs. `tempo` is the host's tempo,
+    /// if it reports one.
+    fn configure_block(&mut self, tempo: Option<f64>, auto_threshold_armed: bool) -> BlockSettings {
+        // With sample accurate automation the buffer is split at every parameter change, so it's
+        // enough to update the gates once per block
+        let threshold_db = self.params.threshold.value();
+        let open_threshold = util::db_to_gain(threshold_db);
+        let close_threshold = util::db_to_gain(threshold_db - self.params.hysteresis.value());
+        let range_db = self.params.range.value();
+        let floor = util::db_to_gain(range_db);
+        // The synced times fall back to the millisecond times when the host doesn't report a tempo
+        let (hold_ms, release_ms) = match tempo {
+            Some(tempo) if self.params.tempo_sync.value() && tempo > 0.0 => (
+                self.params.hold_note.value().duration_ms(tempo),
+                self.params.release_note.value().duration_ms(tempo),
+            ),
+            _ => (self.params.hold.value(), self.params.release.value()),
+        };
+        // In MIDI trigger mode a NoteOff should immediately start the release
+        let midi_trigger = self.params.trigger_source.value() == TriggerSource::Midi;
+        let hold_ms = if midi_trigger { 0.0 } else { hold_ms };
+        for gate in &mut self.gates {
+            gate.set_thresholds(open_threshold, close_threshold);
+            gate.set_floor(floor);
+            gate.set_timing(
+                self.sample_rate,
+                self.params.attack.value(),
+                hold_ms,
+                release_ms
+            );
+        }
+        let ratio = self.params.ratio.value();
+        let knee_db = self.params.knee.value();
+        for expander in &mut self.expanders {
+            expander.set_curve(threshold_db, ratio, knee_db, range_db);
+            expander.set_timing(
+                self.sample_rate,
+                self.params.attack.value(),
+                hold_ms,
+                release_ms
+            );
+        }
+        let gate_mode = self.params.mode.value();
+        let multiband = gate_mode == GateMode::Multiband;
+        if multiband {
+            // The crossover frequencies need to be in ascending order
+            let crossover_1 = self.params.crossover_1.value();
+            let crossover_2 = self.params.crossover_2.value().max(crossover_1);
+            let crossover_3 = self.params.crossover_3.value().max(crossover_2);
+            let num_bands = self.params.num_bands.value() as usize;
+            let crossover_frequencies = [crossover_1, crossover_2, crossover_3];
+            let crossover_frequencies = &crossover_frequencies[..num_bands - 1];
+
+            let hysteresis_db = self.params.hysteresis.value();
+            for multiband_gate in &mut self.multiband_gates {
+                multiband_gate.set_crossover_frequencies(self.sample_rate, crossover_frequencies);
+                for (gate, band_params) in multiband_gate
+                    .gates_mut()
+                    .iter_mut()
+                    .zip(&self.params.bands) {
+                    let band_threshold_db = band_params.threshold.value();
+                    gate.set_thresholds(
+                        util::db_to_gain(band_threshold_db),
+                        util::db_to_gain(band_threshold_db - hysteresis_db)
+                    );
+                    gate.set_floor(util::db_to_gain(band_params.range.value()));
+                    gate.set_timing(
+                        self.sample_rate,
+                        band_params.attack.value(),
+                        hold_ms,
+                        band_params.release.value()
+                    );
+                }
+            }
+            for key_crossover in &mut self.key_crossovers {
+                key_crossover.set_frequencies(self.sample_rate, crossover_frequencies);
+            }
+            for dry_allpass in &mut self.dry_allpasses {
+                dry_allpass.set_frequencies(self.sample_rate, crossover_frequencies);
+            }
+        }
+
+        let num_key_filter_stages = self.params.key_filter_slope.value().num_stages();
+        let key_hpf_stages = if self.params.key_hpf_enabled.value() {
+            num_key_filter_stages
+        } else {
+            0
+        };
+        let key_lpf_stages = if self.params.key_lpf_enabled.value() {
+            num_key_filter_stages
+        } else {
+            0
+        };
+        let key_hpf_frequency = self.params.key_hpf_frequency.value();
+        let key_lpf_frequency = self.params.key_lpf_frequency.value();
+        for key_filter in &mut self.key_filters {
+            key_filter.set_highpass(self.sample_rate, key_hpf_frequency, key_hpf_stages);
+            key_filter.set_lowpass(self.sample_rate, key_lpf_frequency, key_lpf_stages);
+        }
+
+        let lookahead_samples = self.lookahead_samples();
+        BlockSettings {
+            lookahead_samples,
+            dry_delay_samples: self.latency_samples as usize,
+            bypass: self.params.bypass.value(),
+
+            gate_mode,
+            multiband,
+            duck: self.params.duck.value(),
+            threshold_db,
+            floor,
+            key_listen: self.params.key_listen.value(),
+            detector_mode: self.params.detector_mode.value(),
+            stereo_link: self.params.stereo_link.value(),
+            mid_side: self.params.channel_mode.value() == ChannelMode::MidSide,
+            mid_side_gating: self.params.mid_side_gating.value(),
+            auto_threshold_armed,
+
+            midi_trigger,
+            trigger_velocity: self.params.trigger_velocity.value(),
+            trigger_note_filter: self.params.trigger_note_filter.value(),
+            trigger_note: self.params.trigger_note.value() as u8,
+            midi_output: self.params.midi_output.value(),
+            midi_velocity_window_samples: lookahead_samples as u32,
+        }
+    }
+
+    /// Handle the MIDI events up to and including `sample_idx`, so they take effect at the exact
+    /// sample they occur at. `next_event` holds the first event that has not been handled yet.
+    fn handle_midi_events(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        next_event: &mut Option<PluginNoteEvent<Self>>,
+        sample_idx: usize,
+        block: &BlockSettings
+    ) {
+        while let Some(event) = *next_event {
+            if event.timing() > (sample_idx as u32) {
+                break;
+            }
+
+            match event {
+                NoteEvent::NoteOn { note, velocity, .. } => {
+                    if !block.trigger_note_filter || note == block.trigger_note {
+                        self.midi_held_notes[note as usize] = true;
+                        self.midi_velocity = velocity;
+                    }
+                }
+                NoteEvent::NoteOff { note, .. } => {
+                    self.midi_held_notes[note as usize] = false;
+                }
+                _ => (),
+            }
+
+            *next_event = context.next_event();
+        }
+    }
+
+    /// Store the current sample's dry signal in `dry_samples` after delaying it by
+    /// `dry_delay_samples`, and apply the input gain. Shared by all modes.
+    fn process_input(
+        &mut self,
+        channel_samples: &mut ChannelSamples,
+        sample_idx: usize,
+        dry_delay_samples: usize,
+        telemetry_enabled: bool
+    ) {
+        let input_gain = self.params.input_gain.smoothed.next();
+        // The dry signal is taken before the input gain, so bypassing the plugin or setting the
+        // mix to zero results in the unaltered input
+        for ((sample, dry_delay_line), dry_samples) in channel_samples
+            .iter_mut()
+            .zip(&mut self.dry_delay_lines)
+            .zip(&mut self.dry_samples) {
+            dry_samples[sample_idx] = dry_delay_line.process(*sample, dry_delay_samples);
+        }
+        for (sample, telemetry_frame) in channel_samples
+            .iter_mut()
+            .zip(&mut self.telemetry_frames) {
+            *sample *= input_gain;
+            if telemetry_enabled {
+                telemetry_frame.input_level = telemetry_frame.input_level.max(sample.abs());
+            }
+        }
+    }
+
+    /// Run the current sample's key signal through the key filters, the detectors, and the
+    /// envelope followers. The key comes from the sidechain if there is one, and from the main
+    /// input otherwise. The per-channel levels are stored in `detector_levels` and
+    /// `band_detector_levels`, and the levels linked according to the stereo link mode are
+    /// returned. The linked band levels are only computed in multiband mode.
+    fn compute_detector_levels(
+        &mut self,
+        channel_samples: &mut ChannelSamples,
+        sidechain: Option<&[&[f32]]>,
+        sample_idx: usize,
+        block: &BlockSettings
+    ) -> (f32, [f32; MAX_BANDS]) {
+        match sidechain {
+            Some(sidechain) => {
+                for (key_sample, sidechain_channel) in self.key_samples
+                    .iter_mut()
+                    .zip(sidechain.iter()) {
+                    *key_sample = sidechain_channel[sample_idx];
+                }
+                if block.mid_side {
+                    mid_side_encode_in_place(self.key_samples.iter_mut());
+                }
+            }
+            None => {
+                for (key_sample, sample) in self.key_samples
+                    .iter_mut()
+                    .zip(channel_samples.iter_mut()) {
+                    *key_sample = *sample;
+                }
+            }
+        }
+
+        // The key filter only affects the detector, the audio path is never filtered
+        for ((((key_sample, key_filter), detector), envelope_follower), detector_level) in self
+            .key_samples
+            .iter_mut()
+            .zip(&mut self.key_filters)
+            .zip(&mut self.detectors)
+            .zip(&mut self.envelope_followers)
+            .zip(&mut self.detector_levels) {
+            *key_sample = key_filter.process(*key_sample);
+            let level = detector.next(block.detector_mode, *key_sample);
+            *detector_level = envelope_follower.next(level);
+        }
+
+        let linked_detector_level = match block.stereo_link {
+            StereoLink::Max => self.detector_levels.iter().copied().fold(0.0, f32::max),
+            StereoLink::Average => {
+                self.detector_levels.iter().sum::<f32>() /
+                    (self.detector_levels.len().max(1) as f32)
+            }
+            // Each gate picks its own detector channel in `apply_gates()`
+            StereoLink::Independent => 0.0,
+        };
+        if block.auto_threshold_armed {
+            self.auto_threshold.next(match block.stereo_link {
+                StereoLink::Independent => {
+                    self.detector_levels.iter().copied().fold(0.0, f32::max)
+                }
+                StereoLink::Max | StereoLink::Average => linked_detector_level,
+            });
+        }
+
+        let mut linked_band_levels = [0.0; MAX_BANDS];
+        if block.multiband {
+            for (((key_sample, key_crossover), envelope_followers), band_levels) in self
+                .key_samples
+                .iter()
+                .zip(&mut self.key_crossovers)
+                .zip(&mut self.band_envelope_followers)
+                .zip(&mut self.band_detector_levels) {
+                let mut key_bands = [0.0; MAX_BANDS];
+                key_crossover.split(*key_sample, &mut key_bands);
+                for ((band_level, envelope_follower), key_band) in band_levels
+                    .iter_mut()
+                    .zip(envelope_followers.iter_mut())
+                    .zip(key_bands) {
+                    *band_level = envelope_follower.next(key_band);
+                }
+            }
+
+            for (band_idx, linked_band_level) in linked_band_levels.iter_mut().enumerate() {
+                let band_levels = self.band_detector_levels
+                    .iter()
+                    .map(|band_levels| band_levels[band_idx]);
+                *linked_band_level = match block.stereo_link {
+                    StereoLink::Max => band_levels.fold(0.0, f32::max),
+                    StereoLink::Average => {
+                        band_levels.sum::<f32>() /
+                            (self.band_detector_levels.len().max(1) as f32)
+                    }
+                    StereoLink::Independent => 0.0,
+                };
+            }
+        }
+
+        (linked_detector_level, linked_band_levels)
+    }
+
+    /// Run the gates for the current sample and apply their gain to the lookahead delayed audio.
+    /// Returns whether any channel's gate opened, or was re-triggered during its release, on this
+    /// sample. The second value is the highest detector level of the channels whose gate is open,
+    /// or `None` when all of them are closed.
+    fn apply_gates(
+        &mut self,
+        channel_samples: &mut ChannelSamples,
+        linked_detector_level: f32,
+        linked_band_levels: &[f32; MAX_BANDS],
+        block: &BlockSettings,
+        telemetry_enabled: bool
+    ) -> (bool, Option<f32>) {
+        let midi_gate_open = self.midi_held_notes.contains(&true);
+        // The open gain is scaled down by the velocity depending on the velocity sensitivity
+        let midi_open_gain = 1.0 - block.trigger_velocity * (1.0 - self.midi_velocity);
+
+        // Used for the MIDI output, the gate counts as open while any of its channels are open
+        let mut gate_triggered = false;
+        let mut gate_open = false;
+        let mut open_detector_level = 0.0f32;
+        let current_stage = |gate: &Gate, expander: &Expander, multiband_gate: &MultibandGate| {
+            match block.gate_mode {
+                GateMode::Gate => gate.stage(),
+                GateMode::Expander => expander.stage(),
+                GateMode::Multiband => multiband_gate.stage(),
+                GateMode::Spectral => unreachable!(),
+            }
+        };
+
+        // The audio path lags behind the detector by the lookahead time, so the gate can already
+        // be open by the time a transient reaches the output
+        for (channel_idx, sample) in channel_samples.iter_mut().enumerate() {
+            let gate = &mut self.gates[channel_idx];
+            let expander = &mut self.expanders[channel_idx];
+            let multiband_gate = &mut self.multiband_gates[channel_idx];
+            let previous_stage = current_stage(gate, expander, multiband_gate);
+            let detector_level = match block.stereo_link {
+                StereoLink::Independent => self.detector_levels[channel_idx],
+                StereoLink::Max | StereoLink::Average => linked_detector_level,
+            };
+            // In MIDI trigger mode the held notes take the detector's place
+            let gate_level = match (block.midi_trigger, midi_gate_open) {
+                (false, _) => detector_level,
+                (true, true) => f32::INFINITY,
+                (true, false) => 0.0,
+            };
+            let gate_gain = match block.gate_mode {
+                GateMode::Gate => gate.next(gate_level),
+                GateMode::Expander => expander.next(gate_level),
+                GateMode::Multiband => {
+                    let midi_trigger = block.midi_trigger;
+                    let band_levels = match (midi_trigger, midi_gate_open, &block.stereo_link) {
+                        (false, _, StereoLink::Independent) => {
+                            self.band_detector_levels[channel_idx]
+                        }
+                        (false, _, _) => *linked_band_levels,
+                        (true, true, _) => [f32::INFINITY; MAX_BANDS],
+                        (true, false, _) => [0.0; MAX_BANDS],
+                    };
+                    multiband_gate.next(&band_levels, block.duck)
+                }
+                GateMode::Spectral => unreachable!(),
+            };
+            let gate_gain = if block.midi_trigger && !block.multiband {
+                block.floor + (gate_gain - block.floor) * midi_open_gain
+            } else {
+                gate_gain
+            };
+            let stage = current_stage(gate, expander, multiband_gate);
+            // A hit during the release re-triggers the gate, which counts as a new hit
+            gate_triggered |=
+                matches!(previous_stage, GateStage::Closed | GateStage::Release) &&
+                matches!(stage, GateStage::Attack | GateStage::Open);
+            if stage != GateStage::Closed {
+                gate_open = true;
+                open_detector_level = open_detector_level.max(detector_level);
+            }
+            // Ducking mirrors the gain between unity and the floor, so the attack, hold, and
+            // release times keep the same meaning relative to the key signal. The multiband gate
+            // already does this for every band on its own.
+            let gate_gain = if block.duck && !block.multiband {
+                1.0 + block.floor - gate_gain
+            } else {
+                gate_gain
+            };
+
+            // In mid/side mode the first channel holds the mid and the second the side signal
+            let gated = !block.mid_side ||
+                (match block.mid_side_gating {
+                    MidSideGating::Both => true,
+                    MidSideGating::Mid => channel_idx == 0,
+                    MidSideGating::Side => channel_idx == 1,
+                });
+
+            // The key signal goes through the same delay so listening to it doesn't change the
+            // latency
+            let input_sample = if block.key_listen {
+                self.key_samples[channel_idx]
+            } else {
+                *sample
+            };
+            let delayed_sample = self.lookahead_delay_lines[channel_idx].process(
+                input_sample,
+                block.lookahead_samples
+            );
+            *sample = if block.key_listen {
+                delayed_sample
+            } else if block.multiband {
+                multiband_gate.process(delayed_sample, gated)
+            } else if gated {
+                delayed_sample * gate_gain
+            } else {
+                delayed_sample
+            };
+
+            if telemetry_enabled {
+                let telemetry_frame = &mut self.telemetry_frames[channel_idx];
+                telemetry_frame.detector_level = telemetry_frame.detector_level.max(detector_level);
+                if gated {
+                    telemetry_frame.gain = telemetry_frame.gain.min(gate_gain);
+                }
+                telemetry_frame.stage = stage;
+            }
+        }
+
+        (gate_triggered, gate_open.then_some(open_detector_level))
+    }
+
+    /// Send a NoteOn event for every hit that opens or re-triggers the gate, and a NoteOff event
+    /// when the gate closes again or the next hit comes in. `gate_triggered` and
+    /// `open_detector_level` are the values returned by `apply_gates()`. With lookahead the NoteOn
+    /// is delayed by the lookahead time, and its velocity follows the hit's peak within that time.
+    fn send_midi_output(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        sample_idx: usize,
+        gate_triggered: bool,
+        open_detector_level: Option<f32>,
+        block: &BlockSettings
+    ) {
+        let timing = sample_idx as u32;
+        if !block.midi_output {
+            self.midi_output_pending_note = None;
+        }
+        let open_detector_level = open_detector_level.filter(|_| block.midi_output);
+        if gate_triggered && open_detector_level.is_some() {
+            self.end_midi_output_note(context, timing);
+            self.midi_output_pending_note = Some((block.midi_velocity_window_samples, 0.0));
+        }
+
+        if let Some((samples_remaining, peak_level)) = self.midi_output_pending_note {
+            let peak_level = peak_level.max(open_detector_level.unwrap_or(0.0));
+            // A hit that closes the gate again before the end of the window still gets its note
+            if samples_remaining == 0 || open_detector_level.is_none() {
+                let channel = (self.params.midi_output_channel.value() - 1) as u8;
+                let note = self.params.midi_output_note.value() as u8;
+                context.send_event(NoteEvent::NoteOn {
+                    timing,
+                    voice_id: None,
+                    channel,
+                    note,
+                    velocity: midi_output_velocity(peak_level, block.threshold_db),
+                });
+                self.midi_output_note = Some((channel, note));
+                self.midi_output_pending_note = None;
+            } else {
+                self.midi_output_pending_note = Some((samples_remaining - 1, peak_level));
+            }
+        }
+
+        if open_detector_level.is_none() {
+            self.end_midi_output_note(context, timing);
+        }
+    }
+
+    /// Send the NoteOff event for the MIDI output's current note, if there is one.
+    fn end_midi_output_note(&mut self, context: &mut impl ProcessContext<Self>, timing: u32) {
+        if let Some((channel, note)) = self.midi_output_note.take() {
+            context.send_event(NoteEvent::NoteOff {
+                timing,
+                voice_id: None,
+                channel,
+                note,
+                velocity: 0.0,
+            });
+        }
+    }
+
+    /// Mix the processed sample with the dry signal stored by `process_input()`, apply the output
+    /// gain and the bypass crossfade, and feed the result to the peak meters. Shared by all modes.
+    /// In multiband mode the dry signal fades to the crossovers' phase response as the mix goes
+    /// up.
+    fn process_output(
+        &mut self,
+        channel_samples: &mut ChannelSamples,
+        sample_idx: usize,
+        bypass: bool,
+        multiband: bool,
+        detector_mode: DetectorMode,
+        telemetry_enabled: bool
+    ) {
+        let mix = self.params.mix.smoothed.next();
+        let output_gain = self.params.output_gain.smoothed.next();
+        let bypass_fade = self.next_bypass_fade(bypass);
+        for ((sample, dry_samples), dry_allpass) in channel_samples
+            .iter_mut()
+            .zip(&self.dry_samples)
+            .zip(&mut self.dry_allpasses) {
+            let dry = dry_samples[sample_idx];
+            // Fading in the phase response keeps a mix of 0 identical to the dry signal
+            let phase_matched_dry = if multiband {
+                mix_dry_wet(dry, dry_allpass.process(dry), mix)
+            } else {
+                dry
+            };
+            let processed = mix_dry_wet(phase_matched_dry, *sample, mix) * output_gain;
+            *sample = mix_dry_wet(processed, dry, bypass_fade);
+        }
+
+        if telemetry_enabled {
+            for (((sample, telemetry_frame), peak_meter), output_detector) in channel_samples
+                .iter_mut()
+                .zip(&mut self.telemetry_frames)
+                .zip(&mut self.peak_meters)
+                .zip(&mut self.output_detectors) {
+                telemetry_frame.output_level = telemetry_frame.output_level.max(sample.abs());
+                peak_meter.next(output_detector.next(detector_mode, *sample), *sample);
+            }
+        }
+    }
+
+    /// The spectral mode's counterpart to the per-sample gating in `process()`. The input and
+    /// output stages are shared with the other modes.
+    fn process_spectral(
+        &mut self,
+        buffer: &mut Buffer,
+        context: &mut impl ProcessContext<Self>,
+        telemetry_enabled: bool
+    ) {
+        // Nothing in this mode opens or closes the gate, so a note that's still held needs to be
+        // released
+        self.midi_output_pending_note = None;
+        self.end_midi_output_note(context, 0);
+
+        self.spectral_gate.set_parameters(
+            self.sample_rate,
+            self.params.spectral_threshold.value(),
+            util::db_to_gain(self.params.threshold.value()),
+            self.params.spectral_reduction.value(),
+            self.params.spectral_time_smoothing.value(),
+            self.params.spectral_frequency_smoothing.value() as usize
+        );
+
+        let dry_delay_samples = self.latency_samples as usize;
+        let bypass = self.params.bypass.value();
+        let detector_mode = self.params.detector_mode.value();
+        for (sample_idx, mut channel_samples) in buffer.iter_samples().enumerate() {
+            self.process_input(
+                &mut channel_samples,
+                sample_idx,
+                dry_delay_samples,
+                telemetry_enabled
+            );
+        }
+
+        self.spectral_gate.process(buffer);
+
+        for (sample_idx, mut channel_samples) in buffer.iter_samples().enumerate() {
+            self.process_output(
+                &mut channel_samples,
+                sample_idx,
+                bypass,
+                false,
+                detector_mode,
+                telemetry_enabled
+            );
+        }
+        if telemetry_enabled {
+            for (channel_idx, telemetry_frame) in self.telemetry_frames.iter_mut().enumerate() {
+                telemetry_frame.gain = self.spectral_gate.average_gain(channel_idx);
+            }
+        }
+    }
+
+    /// Advance the bypass crossfade by one sample, and return the amount of dry signal that should
+    /// be mixed in.
+    fn next_bypass_fade(&mut self, bypass: bool) -> f32 {
+        self.bypass_fade = if bypass {
+            (self.bypass_fade + self.bypass_fade_step).min(1.0)
+        } else {
+            (self.bypass_fade - self.bypass_fade_step).max(0.0)
+        };
+
+        self.bypass_fade
+    }
+
+    /// Push the current block's telemetry frames and peak meter states to the editor.
+    fn publish_telemetry(&mut self) {
+        self.telemetry.push(&self.telemetry_frames);
+        for (meter_telemetry, peak_meter) in self.telemetry.peak_meters
+            .iter()
+            .zip(&mut self.peak_meters) {
+            meter_telemetry.update(peak_meter);
+        }
+    }
+}
+
+/// Crossfade between the dry and the wet signal. A mix of 0 returns the dry sample exactly, and a
+/// mix of 1 returns the wet sample exactly.
+fn mix_dry_wet(dry: f32, wet: f32, mix: f32) -> f32 {
+    dry * (1.0 - mix) + wet * mix
+}
+
+/// The velocity for the NoteOn event sent when the gate opens. This maps the hit's peak detector
+/// level linearly in decibels from the threshold to 0 dBFS onto the velocity range, so a hit that
+/// barely opens the gate still produces a (quiet) note.
+fn midi_output_velocity(detector_level: f32, threshold_db: f32) -> f32 {
+    let headroom_db = (-threshold_db).max(1.0);
+    let velocity = (util::gain_to_db(detector_level) - threshold_db) / headroom_db;
+
+    velocity.clamp(1.0 / 127.0, 1.0)
+}
+
 impl ClapPlugin for Noiseg8 {
-    const CLAP_ID: &'static str = "com.your-domain.noiseg8";
-    const CLAP_DESCRIPTION: Option<&'static str> = Some("ZMANN.org");
+    // This ID is used by hosts to identify the plugin in saved projects, so it must never change
+    const CLAP_ID: &'static str = "org.zmann.noiseg8";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some(
+        "A noise gate with expander, multiband, and spectral modes"
+    );
     const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
     const CLAP_SUPPORT_URL: Option<&'static str> = None;
 
-    // Don't forget to change these features
-    const CLAP_FEATURES: &'static [ClapFeature] = &[ClapFeature::AudioEffect, ClapFeature::Stereo];
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Mono,
+        ClapFeature::Stereo,
+        ClapFeature::Gate,
+        ClapFeature::Expander,
+    ];
 }
 
 impl Vst3Plugin for Noiseg8 {
@@ -190,4 +2032,36 @@ impl Vst3Plugin for Noiseg8 {
     ];
 }
 
+nih_export_clap!(Noiseg8);
 nih_export_vst3!(Noiseg8);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_mix_is_wet_signal() {
+        for (dry, wet) in [(0.5, -0.25), (-1.0, 1.0e-9), (0.0, 0.75)] {
+            assert_eq!(mix_dry_wet(dry, wet, 1.0).to_bits(), wet.to_bits());
+        }
+    }
+
+    #[test]
+    fn note_durations() {
+        // A quarter note lasts half a second at 120 BPM
+        let cases = [
+            (NoteDivision::Whole, 2000.0),
+            (NoteDivision::Quarter, 500.0),
+            (NoteDivision::QuarterDotted, 750.0),
+            (NoteDivision::EighthTriplet, 500.0 / 3.0),
+            (NoteDivision::Sixteenth, 125.0),
+            (NoteDivision::SixteenthDotted, 187.5),
+        ];
+        for (note, expected_ms) in cases {
+            let duration_ms = note.duration_ms(120.0);
+            assert!((duration_ms - expected_ms).abs() < 1e-3, "{note:?}: {duration_ms} ms");
+        }
+
+        assert_eq!(NoteDivision::Quarter.duration_ms(60.0), 1000.0);
+    }
+}
@@ -0,0 +1,199 @@
+//! NoiseG8 as a standalone application. By default this runs the plugin and its editor using
+//! nih_plug's standalone wrapper, which also accepts `--backend dummy` to run without a sound card.
+//! Passing `--input` and `--output` instead renders a WAV file without opening an audio device or
+//! a window, so the plugin can be tested in headless CI.
+
+use nih_plug::prelude::*;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use noiseg8::render::{ Audio, Automation, Renderer };
+use noiseg8::Noiseg8;
+
+/// The block size for rendering files when `--period-size` is not passed. This matches the
+/// standalone wrapper's default period size.
+const DEFAULT_PERIOD_SIZE: u32 = 512;
+
+const FILE_USAGE: &str = "\
+File rendering options:
+      --input <PATH>           The WAV file to process. Requires --output
+      --output <PATH>          Where to write the processed audio as a 32-bit float WAV file
+      --sample-rate <HZ>       Fail if the input file does not have this sample rate
+      --period-size <SAMPLES>  The number of samples processed at a time [default: 512]
+      --tempo <BPM>            The tempo reported to the plugin. Without it, the tempo synced hold
+                               and release times fall back to milliseconds
+      --param <ID>=<VALUE>     Set a parameter before processing. Booleans are 0 or 1, and enums
+                               are the option's index. Can be repeated";
+
+/// The options for rendering a WAV file. `--sample-rate`, `--period-size`, and `--tempo` are the
+/// standalone wrapper's own flags, and they mean the same thing here.
+struct FileOptions {
+    input: PathBuf,
+    output: PathBuf,
+    sample_rate: Option<f32>,
+    period_size: u32,
+    /// Unlike the standalone wrapper, no tempo is reported unless this is set.
+    tempo: Option<f64>,
+    /// Parameter changes applied at the very first sample.
+    params: Vec<Automation>,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{FILE_USAGE}\n");
+    }
+
+    let succeeded = match parse_file_options(&args[1..]) {
+        Ok(Some(options)) => match render_file(&options) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("Could not render '{}': {err}", options.input.display());
+                false
+            }
+        },
+        Ok(None) => nih_export_standalone_with_args::<Noiseg8, _>(args),
+        Err(err) => {
+            eprintln!("{err}\n\n{FILE_USAGE}");
+            false
+        }
+    };
+
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Parse the command line options for rendering a file. Returns `None` if neither `--input` nor
+/// `--output` was passed, in which case the arguments are meant for the standalone wrapper.
+fn parse_file_options(args: &[String]) -> Result<Option<FileOptions>, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut sample_rate = None;
+    let mut period_size = DEFAULT_PERIOD_SIZE;
+    let mut tempo = None;
+    let mut params = Vec::new();
+    let mut other_args = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // Both `--flag value` and `--flag=value` are accepted, just like for the wrapper's flags
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| format!("Missing a value for '{flag}'"))
+        };
+
+        match flag {
+            "--input" => {
+                input = Some(PathBuf::from(value()?));
+            }
+            "--output" => {
+                output = Some(PathBuf::from(value()?));
+            }
+            "--sample-rate" => {
+                let value = value()?;
+                sample_rate = Some(
+                    value.parse::<f32>().map_err(|_| format!("Invalid sample rate '{value}'"))?
+                );
+            }
+            "--period-size" => {
+                let value = value()?;
+                period_size = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|period_size| *period_size > 0)
+                    .ok_or_else(|| format!("Invalid period size '{value}'"))?;
+            }
+            "--tempo" => {
+                let value = value()?;
+                tempo = Some(
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|tempo| *tempo > 0.0)
+                        .ok_or_else(|| format!("Invalid tempo '{value}'"))?
+                );
+            }
+            "--param" => {
+                let value = value()?;
+                let automation = value
+                    .split_once('=')
+                    .and_then(|(param_id, value)| {
+                        Some(Automation::new(0, param_id, value.parse().ok()?))
+                    })
+                    .ok_or_else(|| format!("Invalid parameter '{value}', expected <ID>=<VALUE>"))?;
+                params.push(automation);
+            }
+            _ => other_args.push(arg),
+        }
+    }
+
+    match (input, output) {
+        (None, None) => Ok(None),
+        (Some(input), Some(output)) => {
+            if let Some(arg) = other_args.first() {
+                return Err(format!("'{arg}' cannot be used when rendering a file"));
+            }
+
+            Ok(Some(FileOptions {
+                input,
+                output,
+                sample_rate,
+                period_size,
+                tempo,
+                params,
+            }))
+        }
+        _ => Err(String::from("--input and --output need to be used together")),
+    }
+}
+
+/// Process the input file and write the result to the output file. The output is compensated for
+/// the plugin's latency, so it lines up with the input and has the same length.
+fn render_file(options: &FileOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let input = Audio::read_wav(&options.input)?;
+    if let Some(sample_rate) = options.sample_rate {
+        if sample_rate != input.sample_rate {
+            let message = format!(
+                "the file's sample rate is {} Hz, resampling is not supported",
+                input.sample_rate
+            );
+            return Err(message.into());
+        }
+    }
+
+    let buffer_config = BufferConfig {
+        sample_rate: input.sample_rate,
+        min_buffer_size: None,
+        max_buffer_size: options.period_size,
+        process_mode: ProcessMode::Offline,
+    };
+    let mut renderer = Renderer::new(&buffer_config, input.channels.len())?;
+    renderer.set_tempo(options.tempo);
+    let mut output = renderer.render(&input, options.period_size as usize, &options.params)?;
+
+    // The latency is only known after the parameters have been applied, so the tail is rendered
+    // separately
+    let latency_samples = renderer.latency_samples() as usize;
+    let silence = Audio {
+        sample_rate: input.sample_rate,
+        channels: vec![vec![0.0; latency_samples]; input.channels.len()],
+    };
+    let tail = renderer.render(&silence, options.period_size as usize, &[])?;
+    for (channel, tail_channel) in output.channels.iter_mut().zip(tail.channels) {
+        channel.extend(tail_channel);
+        channel.drain(..latency_samples);
+    }
+
+    output.write_wav(&options.output)?;
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Peak meters with a peak-hold marker and a clip indicator.
+
+/// The time the peak-hold marker stays in place before it starts following the meter again.
+pub const PEAK_HOLD_MS: f32 = 1500.0;
+/// Samples with an absolute value above this count as clipping.
+pub const CLIP_LEVEL: f32 = 1.0;
+
+/// A single channel's peak meter. All levels are in voltage gain.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PeakMeter {
+    /// The weight the previous level gets on every sample when the signal is decaying.
+    decay_weight: f32,
+    /// The number of samples the peak-hold marker stays in place.
+    hold_samples: u32,
+
+    /// The meter's current level.
+    level: f32,
+    /// The highest level seen within the last `hold_samples` samples.
+    peak_hold: f32,
+    /// The number of samples left before the peak-hold marker is released.
+    peak_hold_samples_remaining: u32,
+    /// Whether a sample exceeded [`CLIP_LEVEL`] since the last call to
+    /// [`take_clipped()`][Self::take_clipped()].
+    clipped: bool,
+}
+
+impl PeakMeter {
+    /// Configure the meter's ballistics. After `decay_ms` milliseconds of pure silence the meter's
+    /// level will have dropped by 12 dB. The peak-hold marker stays in place for `hold_ms`
+    /// milliseconds.
+    pub fn set_timing(&mut self, sample_rate: f32, decay_ms: f32, hold_ms: f32) {
+        let decay_samples = ((sample_rate as f64) * (decay_ms as f64)) / 1000.0;
+        self.decay_weight = 0.25f64.powf(decay_samples.recip()) as f32;
+        self.hold_samples = (sample_rate * hold_ms / 1000.0).round() as u32;
+    }
+
+    /// Reset the meter to silence. This also clears the clip indicator.
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+        self.peak_hold = 0.0;
+        self.peak_hold_samples_remaining = 0;
+        self.clipped = false;
+    }
+
+    /// Feed the detector's level for a sample to the meter, together with the sample itself. The
+    /// meter displays the detector level, while clipping is always judged by the sample value.
+    pub fn next(&mut self, level: f32, sample: f32) {
+        self.level = if level > self.level {
+            level
+        } else {
+            self.level * self.decay_weight + level * (1.0 - self.decay_weight)
+        };
+
+        if self.level >= self.peak_hold {
+            self.peak_hold = self.level;
+            self.peak_hold_samples_remaining = self.hold_samples;
+        } else if self.peak_hold_samples_remaining > 0 {
+            self.peak_hold_samples_remaining -= 1;
+        } else {
+            self.peak_hold = self.level;
+        }
+
+        self.clipped |= sample.abs() > CLIP_LEVEL;
+    }
+
+    /// The meter's current level.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// The level of the peak-hold marker.
+    pub fn peak_hold(&self) -> f32 {
+        self.peak_hold
+    }
+
+    /// Whether the signal clipped since the last time this function was called.
+    pub fn take_clipped(&mut self) -> bool {
+        std::mem::take(&mut self.clipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The meter is fed a level below [`CLIP_LEVEL`] unless a test is about clipping.
+    const LEVEL: f32 = 0.5;
+
+    #[test]
+    fn decays_by_12_db() {
+        const DECAY_MS: f32 = 300.0;
+
+        for sample_rate in [44100.0, 48000.0, 96000.0] {
+            let mut meter = PeakMeter::default();
+            meter.set_timing(sample_rate, DECAY_MS, PEAK_HOLD_MS);
+            meter.next(LEVEL, LEVEL);
+
+            let decay_samples = (sample_rate * DECAY_MS / 1000.0) as usize;
+            for _ in 0..decay_samples {
+                meter.next(0.0, 0.0);
+            }
+
+            let decay_db = 20.0 * (meter.level() / LEVEL).log10();
+            assert!((decay_db - -12.0).abs() < 0.05, "{sample_rate} Hz: {decay_db} dB");
+        }
+    }
+
+    #[test]
+    fn peak_hold_times_out() {
+        let mut meter = PeakMeter::default();
+        // 100 samples of hold
+        meter.set_timing(1000.0, 300.0, 100.0);
+        meter.next(LEVEL, LEVEL);
+
+        for _ in 0..100 {
+            meter.next(0.0, 0.0);
+            assert_eq!(meter.peak_hold(), LEVEL);
+        }
+
+        meter.next(0.0, 0.0);
+        assert!(meter.peak_hold() < LEVEL);
+        assert_eq!(meter.peak_hold(), meter.level());
+    }
+
+    #[test]
+    fn clip_flag_latches_until_taken() {
+        let mut meter = PeakMeter::default();
+        meter.set_timing(1000.0, 300.0, 100.0);
+
+        meter.next(LEVEL, CLIP_LEVEL);
+        assert!(!meter.take_clipped(), "a sample at the clip level doesn't clip");
+
+        // The detector level doesn't matter, only the sample does
+        meter.next(LEVEL, -1.5);
+        meter.next(0.0, 0.0);
+        assert!(meter.take_clipped());
+        assert!(!meter.take_clipped());
+
+        meter.next(LEVEL, 1.5);
+        meter.reset();
+        assert!(!meter.take_clipped());
+    }
+}
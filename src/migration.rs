@@ -0,0 +1,107 @@
+//! Upgrades plugin state saved by older versions of the plugin. Every saved state stores the
+//! schema version it was saved with, and older states are upgraded one version at a time before
+//! nih_plug restores them. This way removing or renaming a parameter doesn't break saved projects.
+
+use nih_plug::prelude::*;
+
+/// The current state schema version. Bump this and add an upgrade function to [`UPGRADES`]
+/// whenever a parameter or persisted field is removed, renamed, or changes meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The persisted field the schema version is stored in.
+pub const SCHEMA_VERSION_KEY: &str = "schema-version";
+
+/// The upgrade functions, indexed by the version they upgrade from. `UPGRADES[n]` turns a version
+/// `n` state into a version `n + 1` state.
+const UPGRADES: [fn(&mut PluginState); SCHEMA_VERSION as usize] = [upgrade_v0_to_v1];
+
+/// Upgrade a saved state to [`SCHEMA_VERSION`]. States saved by a newer version of the plugin are
+/// left as they are, in which case nih_plug skips any parameters it doesn't know about.
+pub fn migrate(state: &mut PluginState) {
+    let version = schema_version(state);
+    if version > SCHEMA_VERSION {
+        nih_log!(
+            "The state was saved with schema version {version}, but only versions up to \
+             {SCHEMA_VERSION} are supported"
+        );
+        return;
+    }
+
+    for upgrade in &UPGRADES[version as usize..] {
+        upgrade(state);
+    }
+
+    // The persisted fields are stored as JSON, and a number's JSON representation is just the
+    // number
+    state.fields.insert(String::from(SCHEMA_VERSION_KEY), SCHEMA_VERSION.to_string());
+}
+
+/// The schema version a state was saved with. States without a version were saved before
+/// versioning was added, and those are version 0.
+fn schema_version(state: &PluginState) -> u32 {
+    state.fields
+        .get(SCHEMA_VERSION_KEY)
+        .and_then(|version| serde_json::from_str(version).ok())
+        .unwrap_or(0)
+}
+
+/// Version 1 removes the placeholder `foobar` parameter.
+fn upgrade_v0_to_v1(state: &mut PluginState) {
+    state.params.remove("foobar");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_fixture(json: &str) -> PluginState {
+        serde_json::from_str(json).expect("The state fixtures are valid")
+    }
+
+    fn assert_f32_param(state: &PluginState, param_id: &str, expected: f32) {
+        match state.params.get(param_id) {
+            Some(ParamValue::F32(value)) => assert_eq!(*value, expected, "{param_id}"),
+            value => panic!("unexpected value for {param_id}: {value:?}"),
+        }
+    }
+
+    #[test]
+    fn upgrade_from_v0() {
+        let mut state = load_fixture(include_str!("../tests/fixtures/state/v0.json"));
+        assert_eq!(schema_version(&state), 0);
+        let num_params = state.params.len();
+
+        migrate(&mut state);
+        assert_eq!(schema_version(&state), SCHEMA_VERSION);
+        assert!(!state.params.contains_key("foobar"));
+        assert_eq!(state.params.len(), num_params - 1);
+
+        // Everything else is restored as it was saved
+        assert_f32_param(&state, "threshold", -42.0);
+        assert_f32_param(&state, "release", 250.0);
+        assert!(state.fields.contains_key("editor-state"));
+        assert!(state.fields.contains_key("noise-profile"));
+    }
+
+    #[test]
+    fn current_version_is_unchanged() {
+        let json = include_str!("../tests/fixtures/state/v1.json");
+        let mut state = load_fixture(json);
+        assert_eq!(schema_version(&state), SCHEMA_VERSION);
+
+        migrate(&mut state);
+        assert_eq!(format!("{state:?}"), format!("{:?}", load_fixture(json)));
+    }
+
+    #[test]
+    fn newer_versions_are_left_alone() {
+        let mut state = load_fixture(include_str!("../tests/fixtures/state/v1.json"));
+        let newer_version = (SCHEMA_VERSION + 1).to_string();
+        state.fields.insert(String::from(SCHEMA_VERSION_KEY), newer_version.clone());
+        state.params.insert(String::from("foobar"), ParamValue::I32(3));
+
+        migrate(&mut state);
+        assert_eq!(state.fields.get(SCHEMA_VERSION_KEY), Some(&newer_version));
+        assert!(state.params.contains_key("foobar"));
+    }
+}
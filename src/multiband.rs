@@ -0,0 +1,305 @@
+//! Crossovers and per-band gates for the multiband mode.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::filter::{ Biquad, BiquadCoefficients };
+use crate::gate::{ Gate, GateStage };
+
+/// The maximum number of bands a signal can be split into.
+pub const MAX_BANDS: usize = 4;
+/// The number of crossovers needed for [`MAX_BANDS`] bands.
+const MAX_CROSSOVERS: usize = MAX_BANDS - 1;
+
+/// Splits a signal into up to [`MAX_BANDS`] bands using fourth order Linkwitz-Riley crossovers. The
+/// bands are split off from the bottom up, and every band is passed through all-pass filters that
+/// match the phase shift of the crossovers above it. This way all bands end up with the same phase
+/// response, and summing them results in an all-pass filtered version of the input with a flat
+/// magnitude response.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crossover {
+    /// The number of bands the signal is split into. Zero is treated the same as one band.
+    num_bands: usize,
+    /// A Linkwitz-Riley low-pass is two identical Butterworth low-passes in series.
+    lowpasses: [[Biquad; 2]; MAX_CROSSOVERS],
+    /// The high-pass counterparts to `lowpasses`.
+    highpasses: [[Biquad; 2]; MAX_CROSSOVERS],
+    /// `allpasses[band_idx][crossover_idx]` compensates band `band_idx` for the phase shift
+    /// introduced by crossover `crossover_idx`. Only the crossovers above the band are used.
+    allpasses: [[Biquad; MAX_CROSSOVERS]; MAX_CROSSOVERS],
+}
+
+/// The all-pass filters a [`Crossover`]'s summed bands are filtered by. Running a signal that
+/// bypasses the crossover through these keeps it in phase with the crossover's output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrossoverAllpass {
+    /// The number of crossovers, and thus the number of all-passes in use.
+    num_crossovers: usize,
+    /// A fourth order Linkwitz-Riley crossover's low and high bands sum to a second order
+    /// all-pass at the crossover frequency.
+    allpasses: [Biquad; MAX_CROSSOVERS],
+}
+
+/// Gates every band of a [`Crossover`] on its own, and sums the gated bands back together.
+#[derive(Debug, Clone, Copy)]
+pub struct MultibandGate {
+    crossover: Crossover,
+    /// One gate per band, from the lowest to the highest band.
+    gates: [Gate; MAX_BANDS],
+    /// The gains computed by the last call to [`next()`][Self::next()].
+    gains: [f32; MAX_BANDS],
+}
+
+impl Crossover {
+    /// Set the crossover frequencies in ascending order. The signal is split into one more band
+    /// than there are frequencies, and frequencies past `MAX_BANDS - 1` are ignored.
+    pub fn set_frequencies(&mut self, sample_rate: f32, frequencies: &[f32]) {
+        let num_crossovers = frequencies.len().min(MAX_CROSSOVERS);
+        self.num_bands = num_crossovers + 1;
+        for (crossover_idx, &frequency) in frequencies[..num_crossovers].iter().enumerate() {
+            let lowpass = BiquadCoefficients::lowpass(sample_rate, frequency, FRAC_1_SQRT_2);
+            for biquad in &mut self.lowpasses[crossover_idx] {
+                biquad.coefficients = lowpass;
+            }
+            let highpass = BiquadCoefficients::highpass(sample_rate, frequency, FRAC_1_SQRT_2);
+            for biquad in &mut self.highpasses[crossover_idx] {
+                biquad.coefficients = highpass;
+            }
+
+            let allpass = BiquadCoefficients::allpass(sample_rate, frequency, FRAC_1_SQRT_2);
+            for band_allpasses in &mut self.allpasses[..crossover_idx] {
+                band_allpasses[crossover_idx].coefficients = allpass;
+            }
+        }
+    }
+
+    /// The number of bands the signal is split into.
+    pub fn num_bands(&self) -> usize {
+        self.num_bands.max(1)
+    }
+
+    /// Clear all of the filters' delay lines.
+    pub fn reset(&mut self) {
+        for biquad in self.lowpasses
+            .iter_mut()
+            .chain(self.highpasses.iter_mut())
+            .flatten()
+            .chain(self.allpasses.iter_mut().flatten()) {
+            biquad.reset();
+        }
+    }
+
+    /// Split a sample into bands. Only the first [`num_bands()`][Self::num_bands()] elements of
+    /// `bands` are written to.
+    pub fn split(&mut self, sample: f32, bands: &mut [f32; MAX_BANDS]) {
+        let num_crossovers = self.num_bands() - 1;
+
+        // Everything above the current crossover still needs to be split further
+        let mut remainder = sample;
+        for ((band, lowpasses), highpasses) in bands[..num_crossovers]
+            .iter_mut()
+            .zip(&mut self.lowpasses)
+            .zip(&mut self.highpasses) {
+            let mut low = remainder;
+            for biquad in lowpasses {
+                low = biquad.process(low);
+            }
+            let mut high = remainder;
+            for biquad in highpasses {
+                high = biquad.process(high);
+            }
+
+            *band = low;
+            remainder = high;
+        }
+        bands[num_crossovers] = remainder;
+
+        for (band_idx, band) in bands[..num_crossovers].iter_mut().enumerate() {
+            for allpass in &mut self.allpasses[band_idx][band_idx + 1..num_crossovers] {
+                *band = allpass.process(*band);
+            }
+        }
+    }
+}
+
+impl CrossoverAllpass {
+    /// Set the crossover frequencies to match. See [`Crossover::set_frequencies()`].
+    pub fn set_frequencies(&mut self, sample_rate: f32, frequencies: &[f32]) {
+        self.num_crossovers = frequencies.len().min(MAX_CROSSOVERS);
+        for (allpass, &frequency) in self.allpasses
+            .iter_mut()
+            .zip(&frequencies[..self.num_crossovers]) {
+            allpass.coefficients = BiquadCoefficients::allpass(
+                sample_rate,
+                frequency,
+                FRAC_1_SQRT_2
+            );
+        }
+    }
+
+    /// Clear all of the filters' delay lines.
+    pub fn reset(&mut self) {
+        for allpass in &mut self.allpasses {
+            allpass.reset();
+        }
+    }
+
+    /// Filter a sample.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.allpasses[..self.num_crossovers]
+            .iter_mut()
+            .fold(sample, |sample, allpass| allpass.process(sample))
+    }
+}
+
+impl Default for MultibandGate {
+    fn default() -> Self {
+        Self {
+            crossover: Crossover::default(),
+            gates: [Gate::default(); MAX_BANDS],
+            gains: [1.0; MAX_BANDS],
+        }
+    }
+}
+
+impl MultibandGate {
+    /// Set the crossover frequencies. See [`Crossover::set_frequencies()`].
+    pub fn set_crossover_frequencies(&mut self, sample_rate: f32, frequencies: &[f32]) {
+        self.crossover.set_frequencies(sample_rate, frequencies);
+    }
+
+    /// The gates for the bands currently in use, from the lowest to the highest band.
+    pub fn gates_mut(&mut self) -> &mut [Gate] {
+        &mut self.gates[..self.crossover.num_bands()]
+    }
+
+    /// The stage of the most open band.
+    pub fn stage(&self) -> GateStage {
+        let num_bands = self.crossover.num_bands();
+        self.gates[..num_bands]
+            .iter()
+            .zip(&self.gains[..num_bands])
+            .max_by(|(_, gain_a), (_, gain_b)| gain_a.total_cmp(gain_b))
+            .map(|(gate, _)| gate.stage())
+            .unwrap_or(GateStage::Closed)
+    }
+
+    /// Clear the crossover and fully close every band's gate.
+    pub fn reset(&mut self) {
+        self.crossover.reset();
+        for gate in &mut self.gates {
+            gate.reset();
+        }
+        self.gains = [1.0; MAX_BANDS];
+    }
+
+    /// Advance every band's gate by one sample using that band's detector level, and return the
+    /// lowest gain among the bands. When `duck` is set the gains are mirrored between unity and the
+    /// band's floor, just like for the broadband gate.
+    pub fn next(&mut self, band_levels: &[f32; MAX_BANDS], duck: bool) -> f32 {
+        let num_bands = self.crossover.num_bands();
+        for ((gain, gate), level) in self.gains[..num_bands]
+            .iter_mut()
+            .zip(&mut self.gates)
+            .zip(band_levels) {
+            let gate_gain = gate.next(*level);
+            *gain = if duck { 1.0 + gate.floor() - gate_gain } else { gate_gain };
+        }
+
+        self.gains[..num_bands].iter().copied().fold(1.0, f32::min)
+    }
+
+    /// Split a sample into bands, apply the gains from the last call to [`next()`][Self::next()],
+    /// and sum the bands back together. If `gated` is false the gains are not applied, but the
+    /// sample still goes through the crossover so its phase matches the gated signal.
+    pub fn process(&mut self, sample: f32, gated: bool) -> f32 {
+        let mut bands = [0.0; MAX_BANDS];
+        self.crossover.split(sample, &mut bands);
+
+        let num_bands = self.crossover.num_bands();
+        if gated {
+            bands[..num_bands]
+                .iter()
+                .zip(&self.gains)
+                .map(|(band, gain)| band * gain)
+                .sum()
+        } else {
+            bands[..num_bands].iter().sum()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const CROSSOVER_FREQUENCIES: [f32; MAX_CROSSOVERS] = [200.0, 2000.0, 8000.0];
+
+    /// The output level for a full scale sine wave relative to the input after the filters have
+    /// settled, in decibels.
+    fn sine_response_db(multiband_gate: &mut MultibandGate, frequency: f32) -> f32 {
+        let num_samples = SAMPLE_RATE as usize;
+        let mut sum_squares = 0.0f64;
+        for sample_idx in 0..num_samples {
+            let phase = std::f32::consts::TAU * frequency * (sample_idx as f32 / SAMPLE_RATE);
+            let output = multiband_gate.process(phase.sin(), true);
+            if sample_idx >= num_samples / 2 {
+                sum_squares += (output * output) as f64;
+            }
+        }
+        let rms = (sum_squares / (num_samples / 2) as f64).sqrt() as f32;
+
+        20.0 * (rms * std::f32::consts::SQRT_2).log10()
+    }
+
+    #[test]
+    fn allpass_matches_the_summed_bands() {
+        for num_bands in 1..=MAX_BANDS {
+            let frequencies = &CROSSOVER_FREQUENCIES[..num_bands - 1];
+            let mut crossover = Crossover::default();
+            crossover.set_frequencies(SAMPLE_RATE, frequencies);
+            let mut crossover_allpass = CrossoverAllpass::default();
+            crossover_allpass.set_frequencies(SAMPLE_RATE, frequencies);
+
+            // An impulse followed by a few sine periods covers the whole spectrum
+            for sample_idx in 0..4800 {
+                let phase = std::f32::consts::TAU * 1000.0 * (sample_idx as f32 / SAMPLE_RATE);
+                let sample = if sample_idx == 0 { 1.0 } else { phase.sin() * 0.5 };
+                let mut bands = [0.0; MAX_BANDS];
+                crossover.split(sample, &mut bands);
+                let summed: f32 = bands[..num_bands].iter().sum();
+                let allpassed = crossover_allpass.process(sample);
+                assert!(
+                    (summed - allpassed).abs() < 1e-4,
+                    "{num_bands} bands, sample {sample_idx}: {summed} != {allpassed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn open_bands_sum_to_unity() {
+        for num_bands in 2..=MAX_BANDS {
+            let mut multiband_gate = MultibandGate::default();
+            multiband_gate.set_crossover_frequencies(
+                SAMPLE_RATE,
+                &CROSSOVER_FREQUENCIES[..num_bands - 1]
+            );
+            for gate in multiband_gate.gates_mut() {
+                gate.set_thresholds(0.0, 0.0);
+                gate.set_floor(0.0);
+                gate.set_timing(SAMPLE_RATE, 0.0, 0.0, 0.0);
+            }
+            let gain = multiband_gate.next(&[1.0; MAX_BANDS], false);
+            assert_eq!(gain, 1.0);
+
+            for frequency in [30.0, 200.0, 700.0, 2000.0, 5000.0, 8000.0, 15000.0] {
+                let response_db = sine_response_db(&mut multiband_gate, frequency);
+                assert!(
+                    response_db.abs() < 0.01,
+                    "{num_bands} bands, {frequency} Hz: {response_db} dB"
+                );
+            }
+        }
+    }
+}
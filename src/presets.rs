@@ -0,0 +1,374 @@
+//! Parameter presets, stored as JSON. The factory presets are compiled into the plugin, and user
+//! presets live in a directory on disk.
+
+use nih_plug::prelude::*;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+use crate::spectral::NoiseProfile;
+use crate::Noiseg8Params;
+
+/// The current preset file format version. Bump this when a change to the format or to the
+/// parameters would change how an older preset sounds.
+pub const PRESET_VERSION: u32 = 1;
+
+/// The file extension for user presets.
+const PRESET_EXTENSION: &str = "json";
+
+/// Parameters that are never stored in or loaded from presets. These are either actions, host
+/// controls, or editor settings rather than part of the sound.
+const EXCLUDED_PARAMS: [&str; 4] = ["bypass", "auto_threshold", "spectral_learn", "meter_decay"];
+
+/// The factory presets' JSON files.
+const FACTORY_PRESETS: [&str; 6] = [
+    include_str!("../presets/kick.json"),
+    include_str!("../presets/snare.json"),
+    include_str!("../presets/toms.json"),
+    include_str!("../presets/vocal.json"),
+    include_str!("../presets/guitar_amp_hum.json"),
+    include_str!("../presets/dialogue.json"),
+];
+
+/// A snapshot of the plugin's parameters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    /// The [`PRESET_VERSION`] the preset was saved with.
+    pub version: u32,
+    pub name: String,
+    /// The parameters' plain values by parameter ID. Booleans are stored as 0 or 1, and enums as
+    /// the variant's index. Parameters missing from a preset are reset to their defaults when the
+    /// preset is loaded.
+    pub params: BTreeMap<String, f32>,
+    /// The spectral gate's learned noise profile, if there was one. Loading a preset without a
+    /// noise profile keeps the current profile, since the profile depends on the recording rather
+    /// than on the settings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise_profile: Option<NoiseProfile>,
+}
+
+/// Stores user presets as individual files in a directory. The file name is the preset's name.
+#[derive(Debug, Clone)]
+pub struct PresetLibrary {
+    dir: PathBuf,
+}
+
+/// Everything that can go wrong while loading or saving presets.
+#[derive(Debug)]
+pub enum PresetError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The preset was saved by a newer version of the plugin.
+    UnsupportedVersion(u32),
+    /// The name is empty or cannot be used as a file name.
+    InvalidName(String),
+    /// A preset with this name already exists.
+    AlreadyExists(String),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetError::Io(error) => write!(f, "{error}"),
+            PresetError::Json(error) => write!(f, "invalid preset: {error}"),
+            PresetError::UnsupportedVersion(version) => {
+                write!(f, "the preset was saved by a newer version (format version {version})")
+            }
+            PresetError::InvalidName(name) => write!(f, "'{name}' is not a valid preset name"),
+            PresetError::AlreadyExists(name) => {
+                write!(f, "a preset called '{name}' already exists")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+impl From<io::Error> for PresetError {
+    fn from(error: io::Error) -> Self {
+        PresetError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for PresetError {
+    fn from(error: serde_json::Error) -> Self {
+        PresetError::Json(error)
+    }
+}
+
+impl Preset {
+    /// Capture the current parameter values and noise profile.
+    pub fn from_params(name: &str, params: &Noiseg8Params) -> Self {
+        let param_values = params
+            .param_map()
+            .into_iter()
+            .filter(|(param_id, _, _)| !EXCLUDED_PARAMS.contains(&param_id.as_str()))
+            .map(|(param_id, param_ptr, _)| {
+                (param_id, unsafe { param_ptr.unmodulated_plain_value() })
+            })
+            .collect();
+        let noise_profile = match params.noise_profile.read() {
+            Ok(noise_profile) if !noise_profile.is_empty() => Some(noise_profile.clone()),
+            _ => None,
+        };
+
+        Self {
+            version: PRESET_VERSION,
+            name: String::from(name),
+            params: param_values,
+            noise_profile,
+        }
+    }
+
+    /// Parse a preset from JSON.
+    pub fn from_json(json: &str) -> Result<Self, PresetError> {
+        let preset: Preset = serde_json::from_str(json)?;
+        if preset.version > PRESET_VERSION {
+            return Err(PresetError::UnsupportedVersion(preset.version));
+        }
+
+        Ok(preset)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Presets can always be serialized")
+    }
+
+    /// Apply the preset to the plugin's parameters. Every parameter is changed through the host
+    /// the same way the editor's widgets change them, so the host records the changes for undo
+    /// and automation. Unknown parameters are ignored. Returns whether the noise profile was
+    /// replaced, in which case the audio thread still needs to pick up the new profile.
+    pub fn apply(&self, params: &Noiseg8Params, setter: &ParamSetter) -> bool {
+        for (param_id, param_ptr, _) in params.param_map() {
+            if EXCLUDED_PARAMS.contains(&param_id.as_str()) {
+                continue;
+            }
+
+            unsafe {
+                let normalized = match self.params.get(&param_id) {
+                    Some(plain) => param_ptr.preview_normalized(*plain),
+                    None => param_ptr.default_normalized_value(),
+                };
+                setter.raw_context.raw_begin_set_parameter(param_ptr);
+                setter.raw_context.raw_set_parameter_normalized(param_ptr, normalized);
+                setter.raw_context.raw_end_set_parameter(param_ptr);
+            }
+        }
+
+        let Some(preset_noise_profile) = &self.noise_profile else {
+            return false;
+        };
+        match params.noise_profile.write() {
+            Ok(mut noise_profile) => {
+                noise_profile.copy_from(preset_noise_profile);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// The presets that ship with the plugin.
+pub fn factory_presets() -> Vec<Preset> {
+    FACTORY_PRESETS.iter()
+        .map(|json| Preset::from_json(json).expect("The factory presets are valid"))
+        .collect()
+}
+
+impl PresetLibrary {
+    /// Store user presets in `dir`. The directory is created when the first preset is saved.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The library in the platform's usual location for user data, if that location can be
+    /// determined.
+    pub fn user_library() -> Option<Self> {
+        let data_dir = if cfg!(target_os = "windows") {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            std::env::var_os("HOME").map(|home| {
+                Path::new(&home).join("Library").join("Application Support")
+            })
+        } else {
+            std::env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share"))
+                })
+        }?;
+
+        Some(Self::new(data_dir.join("NoiseG8").join("Presets")))
+    }
+
+    /// The names of all user presets, sorted alphabetically. A missing directory counts as an
+    /// empty library.
+    pub fn names(&self) -> Result<Vec<String>, PresetError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == PRESET_EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(String::from(name));
+                }
+            }
+        }
+        names.sort_by_key(|name| name.to_lowercase());
+
+        Ok(names)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Preset, PresetError> {
+        let json = fs::read_to_string(self.path(name)?)?;
+        let mut preset = Preset::from_json(&json)?;
+        // The file name wins if the file was renamed by hand
+        preset.name = String::from(name);
+
+        Ok(preset)
+    }
+
+    /// Save a preset under its name, replacing any existing preset with that name.
+    pub fn save(&self, preset: &Preset) -> Result<(), PresetError> {
+        let path = self.path(&preset.name)?;
+        fs::create_dir_all(&self.dir)?;
+        fs::write(path, preset.to_json())?;
+
+        Ok(())
+    }
+
+    /// Rename a preset. Fails if there already is a preset with the new name.
+    pub fn rename(&self, old_name: &str, new_name: &str) -> Result<(), PresetError> {
+        let mut preset = self.load(old_name)?;
+        let new_path = self.path(new_name)?;
+        if new_path.exists() {
+            return Err(PresetError::AlreadyExists(String::from(new_name)));
+        }
+
+        preset.name = String::from(new_name);
+        fs::write(new_path, preset.to_json())?;
+        fs::remove_file(self.path(old_name)?)?;
+
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), PresetError> {
+        Ok(fs::remove_file(self.path(name)?)?)
+    }
+
+    /// The path to the file for a preset. Names that cannot be used as a file name on every
+    /// platform are rejected, so a preset saved on one platform can also be loaded on another.
+    fn path(&self, name: &str) -> Result<PathBuf, PresetError> {
+        let valid = !name.trim().is_empty() &&
+            !name.starts_with('.') &&
+            !name.ends_with(['.', ' ']) &&
+            !name.contains(['/', '\\', ':', '*', '?', '"', '<', '>', '|']) &&
+            !name.chars().any(char::is_control);
+        if !valid {
+            return Err(PresetError::InvalidName(String::from(name)));
+        }
+
+        Ok(self.dir.join(format!("{name}.{PRESET_EXTENSION}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, empty directory for a test's presets.
+    fn test_library(test_name: &str) -> PresetLibrary {
+        let dir = std::env::temp_dir().join(format!("noiseg8-presets-{test_name}"));
+        let _ = fs::remove_dir_all(&dir);
+
+        PresetLibrary::new(dir)
+    }
+
+    #[test]
+    fn factory_presets_are_valid() {
+        let params = Noiseg8Params::default();
+        let param_map = params.param_map();
+
+        let presets = factory_presets();
+        assert_eq!(presets.len(), FACTORY_PRESETS.len());
+        for preset in presets {
+            assert_eq!(preset.version, PRESET_VERSION, "{}", preset.name);
+            for (param_id, plain) in &preset.params {
+                let (_, param_ptr, _) = param_map
+                    .iter()
+                    .find(|(id, _, _)| id == param_id)
+                    .unwrap_or_else(|| panic!("{}: unknown parameter '{param_id}'", preset.name));
+                assert!(!EXCLUDED_PARAMS.contains(&param_id.as_str()), "{}", preset.name);
+
+                // Values outside of the parameter's range would be clamped when loading
+                let round_trip = unsafe {
+                    param_ptr.preview_plain(param_ptr.preview_normalized(*plain))
+                };
+                assert!(
+                    (round_trip - plain).abs() <= plain.abs() * 1e-4,
+                    "{}: {param_id} = {plain} is out of range",
+                    preset.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let params = Noiseg8Params::default();
+        let preset = Preset::from_params("Round Trip", &params);
+        assert!(preset.params.contains_key("threshold"));
+        assert!(!preset.params.contains_key("bypass"));
+
+        assert_eq!(Preset::from_json(&preset.to_json()).unwrap(), preset);
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let json = format!(
+            r#"{{ "version": {}, "name": "Future", "params": {{}} }}"#,
+            PRESET_VERSION + 1
+        );
+        assert!(matches!(Preset::from_json(&json), Err(PresetError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn save_rename_delete() {
+        let library = test_library("save-rename-delete");
+        assert!(library.names().unwrap().is_empty());
+
+        let mut preset = factory_presets().remove(0);
+        preset.name = String::from("My Kick");
+        library.save(&preset).unwrap();
+        assert_eq!(library.names().unwrap(), ["My Kick"]);
+        assert_eq!(library.load("My Kick").unwrap(), preset);
+
+        let other_preset = Preset {
+            name: String::from("Other"),
+            ..preset.clone()
+        };
+        library.save(&other_preset).unwrap();
+        let result = library.rename("My Kick", "Other");
+        assert!(matches!(result, Err(PresetError::AlreadyExists(_))));
+        library.rename("My Kick", "Big Kick").unwrap();
+        assert_eq!(library.names().unwrap(), ["Big Kick", "Other"]);
+        assert_eq!(library.load("Big Kick").unwrap().params, preset.params);
+
+        library.delete("Other").unwrap();
+        assert_eq!(library.names().unwrap(), ["Big Kick"]);
+
+        let invalid_preset = Preset {
+            name: String::from("a/b"),
+            ..preset
+        };
+        assert!(matches!(library.save(&invalid_preset), Err(PresetError::InvalidName(_))));
+    }
+}
@@ -0,0 +1,1162 @@
+//! Offline rendering for regression tests and benchmarks. nih_plug does not let plugins construct
+//! their own process contexts or set parameter values, so the renderer loads the plugin's CLAP
+//! export in-process and acts as a minimal CLAP host. This way the plugin is initialized and
+//! processed by the same wrapper a DAW would use, parameter automation goes through the regular
+//! sample accurate event path, and the result does not depend on anything a real host would not
+//! also do.
+
+use clap_sys::audio_buffer::clap_audio_buffer;
+use clap_sys::events::{
+    clap_event_header,
+    clap_event_note,
+    clap_event_param_value,
+    clap_event_transport,
+    clap_input_events,
+    clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID,
+    CLAP_EVENT_NOTE_OFF,
+    CLAP_EVENT_NOTE_ON,
+    CLAP_EVENT_PARAM_VALUE,
+    CLAP_EVENT_TRANSPORT,
+    CLAP_TRANSPORT_HAS_TEMPO,
+    CLAP_TRANSPORT_IS_PLAYING,
+};
+use clap_sys::ext::audio_ports_config::{
+    clap_plugin_audio_ports_config,
+    CLAP_EXT_AUDIO_PORTS_CONFIG,
+};
+use clap_sys::ext::latency::{ clap_plugin_latency, CLAP_EXT_LATENCY };
+use clap_sys::ext::params::{ clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS };
+use clap_sys::ext::render::{
+    clap_plugin_render,
+    CLAP_EXT_RENDER,
+    CLAP_RENDER_OFFLINE,
+    CLAP_RENDER_REALTIME,
+};
+use clap_sys::factory::plugin_factory::{ clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID };
+use clap_sys::host::clap_host;
+use clap_sys::id::clap_id;
+use clap_sys::plugin::clap_plugin;
+use clap_sys::process::{ clap_process, CLAP_PROCESS_ERROR };
+use clap_sys::version::CLAP_VERSION;
+use nih_plug::prelude::*;
+use std::collections::HashMap;
+use std::ffi::{ c_char, c_void, CStr, CString };
+use std::fmt;
+use std::path::Path;
+use std::ptr;
+
+use crate::{ Noiseg8, Noiseg8Params };
+
+/// Multichannel audio with one vector per channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub sample_rate: f32,
+    pub channels: Vec<Vec<f32>>,
+}
+
+/// A single parameter change, applied at an exact sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Automation {
+    /// The sample the change takes effect at, counting from the start of the rendered audio.
+    pub sample: usize,
+    /// The parameter's ID, e.g. `threshold` or `threshold_2` for the second band's threshold.
+    pub param_id: String,
+    /// The new plain value. Booleans are 0 or 1, and enums are the variant's index.
+    pub value: f32,
+}
+
+/// A note event sent by the plugin while rendering.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderedNote {
+    /// The sample the event was sent at, counted from the start of the rendered audio.
+    pub sample: usize,
+    /// Whether this is a NoteOn event. Otherwise it's a NoteOff event.
+    pub note_on: bool,
+    pub channel: u8,
+    pub note: u8,
+    pub velocity: f32,
+}
+
+/// Everything that can go wrong while rendering.
+#[derive(Debug)]
+pub enum RenderError {
+    Wav(hound::Error),
+    /// The plugin does not have an audio IO layout for this number of channels.
+    UnsupportedChannelCount(usize),
+    /// The automation refers to a parameter that does not exist.
+    UnknownParameter(String),
+    /// The plugin returned an error or rejected a call from the host.
+    Plugin(&'static str),
+}
+
+/// Hosts the plugin's CLAP build in-process. The plugin is activated when the renderer is created,
+/// and destroyed when it's dropped.
+pub struct Renderer {
+    /// The plugin holds on to a pointer to this, so it needs a stable address.
+    host: Box<clap_host>,
+    plugin: *const clap_plugin,
+    /// Maps parameter IDs to their CLAP counterparts.
+    params: HashMap<String, ParamInfo>,
+    /// The plugin's default parameters, only used for converting plain values to normalized
+    /// values. `params` contains pointers into this object.
+    plugin_params: Box<Noiseg8Params>,
+
+    sample_rate: f32,
+    max_block_size: usize,
+    /// The sidechain input for the current block, one vector of `max_block_size` samples per
+    /// sidechain channel.
+    sidechain: Vec<Vec<f32>>,
+    /// The number of samples processed so far.
+    steady_time: i64,
+    /// The tempo reported to the plugin, if any.
+    tempo: Option<f64>,
+    /// The note events sent by the plugin during the last render.
+    notes: Vec<RenderedNote>,
+}
+
+/// The context for the output event callbacks.
+struct OutputEvents<'a> {
+    /// The position of the current block in the rendered audio.
+    block_start: usize,
+    notes: &'a mut Vec<RenderedNote>,
+}
+
+/// A parameter as seen from the CLAP side.
+struct ParamInfo {
+    clap_id: clap_id,
+    param_ptr: ParamPtr,
+    min_value: f64,
+    max_value: f64,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Wav(error) => write!(f, "{error}"),
+            RenderError::UnsupportedChannelCount(num_channels) => {
+                write!(f, "{num_channels} channel audio is not supported")
+            }
+            RenderError::UnknownParameter(param_id) => write!(f, "unknown parameter '{param_id}'"),
+            RenderError::Plugin(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<hound::Error> for RenderError {
+    fn from(error: hound::Error) -> Self {
+        RenderError::Wav(error)
+    }
+}
+
+impl Audio {
+    /// The length of the audio in samples.
+    pub fn len(&self) -> usize {
+        self.channels.first().map(Vec::len).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read a WAV file. Integer samples are scaled to `[-1, 1]`.
+    pub fn read_wav(path: impl AsRef<Path>) -> Result<Self, RenderError> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let num_channels = spec.channels as usize;
+
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = ((1u32 << (spec.bits_per_sample - 1)) as f32).recip();
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        let mut channels = vec![Vec::with_capacity(samples.len() / num_channels); num_channels];
+        for frame in samples.chunks_exact(num_channels) {
+            for (channel, sample) in channels.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+        }
+
+        Ok(Self {
+            sample_rate: spec.sample_rate as f32,
+            channels,
+        })
+    }
+
+    /// Write the audio to a 32-bit floating point WAV file, so it can be read back bit for bit.
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> Result<(), RenderError> {
+        let spec = hound::WavSpec {
+            channels: self.channels.len() as u16,
+            sample_rate: self.sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for sample_idx in 0..self.len() {
+            for channel in &self.channels {
+                writer.write_sample(channel[sample_idx])?;
+            }
+        }
+
+        Ok(writer.finalize()?)
+    }
+}
+
+impl Automation {
+    pub fn new(sample: usize, param_id: &str, value: f32) -> Self {
+        Self {
+            sample,
+            param_id: String::from(param_id),
+            value,
+        }
+    }
+}
+
+impl Renderer {
+    /// Instantiate and activate the plugin for `num_channels` channel audio. The buffer config's
+    /// sample rate and buffer sizes are passed to the plugin's `initialize()` function, and the
+    /// process mode decides whether the plugin is told it's rendering offline.
+    pub fn new(buffer_config: &BufferConfig, num_channels: usize) -> Result<Self, RenderError> {
+        // The layouts with a sidechain input are used so the sidechain code paths are covered as
+        // well. The sidechain is silent unless it's passed to `render_with_sidechain()`.
+        let (layout_idx, layout) = Noiseg8::AUDIO_IO_LAYOUTS
+            .iter()
+            .enumerate()
+            .find(|(_, layout)| {
+                layout.main_input_channels.map(NonZeroU32::get) == Some(num_channels as u32) &&
+                    !layout.aux_input_ports.is_empty()
+            })
+            .ok_or(RenderError::UnsupportedChannelCount(num_channels))?;
+        let num_sidechain_channels = layout.aux_input_ports[0].get() as usize;
+
+        let host = Box::new(clap_host {
+            clap_version: CLAP_VERSION,
+            host_data: ptr::null_mut(),
+            name: c_str(b"NoiseG8 Renderer\0"),
+            vendor: c_str(b"ZMANN\0"),
+            url: c_str(b"\0"),
+            version: c_str(b"0.0.0\0"),
+            get_extension: Some(host_get_extension),
+            request_restart: Some(host_request),
+            request_process: Some(host_request),
+            request_callback: Some(host_request),
+        });
+
+        let plugin_id = CString::new(Noiseg8::CLAP_ID).unwrap();
+        let plugin = unsafe {
+            let entry = &crate::clap_entry;
+            if !(entry.init.unwrap())(c_str(b"\0")) {
+                return Err(RenderError::Plugin("could not initialize the CLAP entry point"));
+            }
+            let factory = (entry.get_factory.unwrap())(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as
+                *const clap_plugin_factory;
+            if factory.is_null() {
+                return Err(RenderError::Plugin("the plugin does not have a plugin factory"));
+            }
+
+            ((*factory).create_plugin.unwrap())(factory, &*host, plugin_id.as_ptr())
+        };
+        if plugin.is_null() {
+            return Err(RenderError::Plugin("could not create the plugin"));
+        }
+
+        // From here on the plugin is destroyed by `Drop` if anything fails
+        let plugin_params = Box::new(Noiseg8Params::default());
+        let mut renderer = Self {
+            host,
+            plugin,
+            params: HashMap::new(),
+            plugin_params,
+
+            sample_rate: buffer_config.sample_rate,
+            max_block_size: buffer_config.max_buffer_size as usize,
+            sidechain: vec![
+                vec![0.0; buffer_config.max_buffer_size as usize];
+                num_sidechain_channels
+            ],
+            steady_time: 0,
+            tempo: None,
+            notes: Vec::new(),
+        };
+
+        unsafe {
+            if !((*plugin).init.unwrap())(plugin) {
+                return Err(RenderError::Plugin("could not initialize the plugin"));
+            }
+
+            let audio_ports_config = renderer.extension::<clap_plugin_audio_ports_config>(
+                CLAP_EXT_AUDIO_PORTS_CONFIG
+            );
+            let layout_selected = !audio_ports_config.is_null() &&
+                ((*audio_ports_config).select.unwrap())(plugin, layout_idx as clap_id);
+            if !layout_selected {
+                return Err(RenderError::UnsupportedChannelCount(num_channels));
+            }
+
+            let render = renderer.extension::<clap_plugin_render>(CLAP_EXT_RENDER);
+            if !render.is_null() {
+                let render_mode = match buffer_config.process_mode {
+                    ProcessMode::Offline => CLAP_RENDER_OFFLINE,
+                    ProcessMode::Realtime | ProcessMode::Buffered => CLAP_RENDER_REALTIME,
+                };
+                ((*render).set.unwrap())(plugin, render_mode);
+            }
+
+            renderer.params = renderer.param_infos()?;
+
+            let activated = ((*plugin).activate.unwrap())(
+                plugin,
+                buffer_config.sample_rate as f64,
+                buffer_config.min_buffer_size.unwrap_or(1),
+                buffer_config.max_buffer_size
+            );
+            if !activated {
+                return Err(RenderError::Plugin("could not activate the plugin"));
+            }
+            if !((*plugin).start_processing.unwrap())(plugin) {
+                return Err(RenderError::Plugin("could not start processing"));
+            }
+        }
+
+        Ok(renderer)
+    }
+
+    /// The latency currently reported by the plugin, in samples.
+    pub fn latency_samples(&self) -> u32 {
+        unsafe {
+            let latency = self.extension::<clap_plugin_latency>(CLAP_EXT_LATENCY);
+            if latency.is_null() {
+                0
+            } else {
+                ((*latency).get.unwrap())(self.plugin)
+            }
+        }
+    }
+
+    /// Report a playing transport at `tempo` beats per minute to the plugin, or no transport
+    /// information at all if `tempo` is `None`. The latter is the default, just like when
+    /// rendering in a host that doesn't provide a tempo.
+    pub fn set_tempo(&mut self, tempo: Option<f64>) {
+        self.tempo = tempo;
+    }
+
+    /// The note events the plugin sent during the last render, in the order they were sent.
+    pub fn notes(&self) -> &[RenderedNote] {
+        &self.notes
+    }
+
+    /// Process `input` in blocks of `block_size` samples, applying the automation at the exact
+    /// samples it's scheduled for. The last block may be shorter. The output is not compensated
+    /// for the plugin's latency. Rendering again continues where the last render stopped.
+    pub fn render(
+        &mut self,
+        input: &Audio,
+        block_size: usize,
+        automation: &[Automation]
+    ) -> Result<Audio, RenderError> {
+        self.render_with_sidechain(input, None, block_size, automation)
+    }
+
+    /// The same as [`render()`][Self::render()], but with `sidechain` on the sidechain input
+    /// instead of silence. The sidechain needs to have as many channels and samples as the input.
+    pub fn render_with_sidechain(
+        &mut self,
+        input: &Audio,
+        sidechain: Option<&Audio>,
+        block_size: usize,
+        automation: &[Automation]
+    ) -> Result<Audio, RenderError> {
+        if let Some(sidechain) = sidechain {
+            assert_eq!(
+                sidechain.channels.len(),
+                self.sidechain.len(),
+                "the sidechain's channel count does not match"
+            );
+            assert_eq!(sidechain.len(), input.len(), "the sidechain's length does not match");
+        }
+        assert!(
+            block_size > 0 && block_size <= self.max_block_size,
+            "the block size must be between 1 and the maximum buffer size"
+        );
+        assert_eq!(input.sample_rate, self.sample_rate, "the sample rate does not match");
+
+        let mut automation_events = automation
+            .iter()
+            .map(|automation| {
+                let param = self.params
+                    .get(&automation.param_id)
+                    .ok_or_else(|| RenderError::UnknownParameter(automation.param_id.clone()))?;
+                let normalized = unsafe { param.param_ptr.preview_normalized(automation.value) };
+                let value =
+                    param.min_value + (normalized as f64) * (param.max_value - param.min_value);
+
+                Ok((automation.sample, param.clap_id, value))
+            })
+            .collect::<Result<Vec<_>, RenderError>>()?;
+        // Changes scheduled for the same sample are applied in the order they were listed in
+        automation_events.sort_by_key(|(sample, _, _)| *sample);
+        let mut automation_events = automation_events.into_iter().peekable();
+
+        // The plugin processes the audio in place
+        let mut output = input.clone();
+        self.notes.clear();
+        let mut block_events: Vec<clap_event_param_value> = Vec::new();
+        for block_start in (0..output.len()).step_by(block_size) {
+            let block_end = (block_start + block_size).min(output.len());
+
+            block_events.clear();
+            while let Some((sample, param_id, value)) =
+                automation_events.next_if(|(sample, _, _)| *sample < block_end)
+            {
+                block_events.push(param_value_event(
+                    sample.saturating_sub(block_start) as u32,
+                    param_id,
+                    value
+                ));
+            }
+
+            for (channel_idx, sidechain_channel) in self.sidechain.iter_mut().enumerate() {
+                let sidechain_block = &mut sidechain_channel[..block_end - block_start];
+                match sidechain {
+                    Some(sidechain) => sidechain_block.copy_from_slice(
+                        &sidechain.channels[channel_idx][block_start..block_end]
+                    ),
+                    None => sidechain_block.fill(0.0),
+                }
+            }
+            let mut main_channels: Vec<*mut f32> = output.channels
+                .iter_mut()
+                .map(|channel| channel[block_start..block_end].as_mut_ptr())
+                .collect();
+            self.process_block(
+                &mut main_channels,
+                block_start,
+                block_end - block_start,
+                &block_events
+            )?;
+        }
+
+        Ok(output)
+    }
+
+    /// Process a single block in place. `main_channels` contains a pointer to `num_samples`
+    /// samples for every main channel, and `block_start` is the block's position in the rendered
+    /// audio.
+    fn process_block(
+        &mut self,
+        main_channels: &mut [*mut f32],
+        block_start: usize,
+        num_samples: usize,
+        events: &[clap_event_param_value]
+    ) -> Result<(), RenderError> {
+        let mut sidechain_channels: Vec<*mut f32> = self.sidechain
+            .iter_mut()
+            .map(|channel| channel.as_mut_ptr())
+            .collect();
+        let inputs = [
+            audio_buffer(main_channels),
+            audio_buffer(&mut sidechain_channels),
+        ];
+        let mut outputs = [audio_buffer(main_channels)];
+
+        // The event callbacks receive a pointer to the slice through the context pointer
+        let in_events = clap_input_events {
+            ctx: &events as *const &[clap_event_param_value] as *mut c_void,
+            size: Some(input_events_size),
+            get: Some(input_events_get),
+        };
+        // Note events are collected in `self.notes`, anything else is discarded
+        let mut output_events = OutputEvents {
+            block_start,
+            notes: &mut self.notes,
+        };
+        let out_events = clap_output_events {
+            ctx: &mut output_events as *mut OutputEvents as *mut c_void,
+            try_push: Some(output_events_try_push),
+        };
+
+        let transport = self.tempo.map(transport_event);
+
+        let process = clap_process {
+            steady_time: self.steady_time,
+            frames_count: num_samples as u32,
+            transport: transport
+                .as_ref()
+                .map_or(ptr::null(), |transport| transport as *const clap_event_transport),
+            audio_inputs: inputs.as_ptr(),
+            audio_outputs: outputs.as_mut_ptr(),
+            audio_inputs_count: inputs.len() as u32,
+            audio_outputs_count: outputs.len() as u32,
+            in_events: &in_events,
+            out_events: &out_events,
+        };
+        let status = unsafe { ((*self.plugin).process.unwrap())(self.plugin, &process) };
+        self.steady_time += num_samples as i64;
+
+        if status == CLAP_PROCESS_ERROR {
+            Err(RenderError::Plugin("the plugin returned an error while processing"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Query one of the plugin's extensions. Returns a null pointer if the plugin does not support
+    /// it.
+    unsafe fn extension<T>(&self, id: &CStr) -> *const T {
+        ((*self.plugin).get_extension.unwrap())(self.plugin, id.as_ptr()) as *const T
+    }
+
+    /// Match the plugin's parameters up with the CLAP parameters. The wrapper exposes the
+    /// parameters in the same order as [`Params::param_map()`].
+    unsafe fn param_infos(&self) -> Result<HashMap<String, ParamInfo>, RenderError> {
+        let params = self.extension::<clap_plugin_params>(CLAP_EXT_PARAMS);
+        if params.is_null() {
+            return Err(RenderError::Plugin("the plugin does not expose its parameters"));
+        }
+
+        let mut param_infos = HashMap::new();
+        for (param_idx, (param_id, param_ptr, _)) in self.plugin_params
+            .param_map()
+            .into_iter()
+            .enumerate() {
+            let mut info: clap_param_info = std::mem::zeroed();
+            if !((*params).get_info.unwrap())(self.plugin, param_idx as u32, &mut info) {
+                return Err(RenderError::Plugin("could not query the plugin's parameters"));
+            }
+            if CStr::from_ptr(info.name.as_ptr()).to_str() != Ok(param_ptr.name()) {
+                return Err(RenderError::Plugin("the plugin's parameters are out of order"));
+            }
+
+            param_infos.insert(param_id, ParamInfo {
+                clap_id: info.id,
+                param_ptr,
+                min_value: info.min_value,
+                max_value: info.max_value,
+            });
+        }
+
+        Ok(param_infos)
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            // Stopping and deactivating a plugin that was never started is a no-op for the wrapper
+            ((*self.plugin).stop_processing.unwrap())(self.plugin);
+            ((*self.plugin).deactivate.unwrap())(self.plugin);
+            ((*self.plugin).destroy.unwrap())(self.plugin);
+            (crate::clap_entry.deinit.unwrap())();
+        }
+    }
+}
+
+fn c_str(bytes: &'static [u8]) -> *const c_char {
+    debug_assert_eq!(bytes.last(), Some(&0));
+    bytes.as_ptr() as *const c_char
+}
+
+fn audio_buffer(channels: &mut [*mut f32]) -> clap_audio_buffer {
+    clap_audio_buffer {
+        data32: channels.as_mut_ptr(),
+        data64: ptr::null_mut(),
+        channel_count: channels.len() as u32,
+        latency: 0,
+        constant_mask: 0,
+    }
+}
+
+fn param_value_event(timing: u32, param_id: clap_id, value: f64) -> clap_event_param_value {
+    clap_event_param_value {
+        header: clap_event_header {
+            size: std::mem::size_of::<clap_event_param_value>() as u32,
+            time: timing,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: CLAP_EVENT_PARAM_VALUE,
+            flags: 0,
+        },
+        param_id,
+        cookie: ptr::null_mut(),
+        note_id: -1,
+        port_index: -1,
+        channel: -1,
+        key: -1,
+        value,
+    }
+}
+
+/// A transport that only reports the tempo, and that playback is running.
+fn transport_event(tempo: f64) -> clap_event_transport {
+    clap_event_transport {
+        header: clap_event_header {
+            size: std::mem::size_of::<clap_event_transport>() as u32,
+            time: 0,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: CLAP_EVENT_TRANSPORT,
+            flags: 0,
+        },
+        flags: CLAP_TRANSPORT_HAS_TEMPO | CLAP_TRANSPORT_IS_PLAYING,
+        song_pos_beats: 0,
+        song_pos_seconds: 0,
+        tempo,
+        tempo_inc: 0.0,
+        loop_start_beats: 0,
+        loop_end_beats: 0,
+        loop_start_seconds: 0,
+        loop_end_seconds: 0,
+        bar_start: 0,
+        bar_number: 0,
+        tsig_num: 4,
+        tsig_denom: 4,
+    }
+}
+
+unsafe extern "C" fn host_get_extension(
+    _host: *const clap_host,
+    _extension_id: *const c_char
+) -> *const c_void {
+    ptr::null()
+}
+
+/// Restarts, process calls, and main thread callbacks can all safely be ignored while rendering.
+unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+unsafe extern "C" fn input_events_size(list: *const clap_input_events) -> u32 {
+    let events = &**((*list).ctx as *const &[clap_event_param_value]);
+    events.len() as u32
+}
+
+unsafe extern "C" fn input_events_get(
+    list: *const clap_input_events,
+    index: u32
+) -> *const clap_event_header {
+    let events = &**((*list).ctx as *const &[clap_event_param_value]);
+    events
+        .get(index as usize)
+        .map_or(ptr::null(), |event| &event.header as *const clap_event_header)
+}
+
+unsafe extern "C" fn output_events_try_push(
+    list: *const clap_output_events,
+    event: *const clap_event_header
+) -> bool {
+    let output_events = &mut *((*list).ctx as *mut OutputEvents);
+    let note_on = (*event).type_ == CLAP_EVENT_NOTE_ON;
+    if (*event).space_id == CLAP_CORE_EVENT_SPACE_ID &&
+        (note_on || (*event).type_ == CLAP_EVENT_NOTE_OFF)
+    {
+        let event = &*(event as *const clap_event_note);
+        output_events.notes.push(RenderedNote {
+            sample: output_events.block_start + event.header.time as usize,
+            note_on,
+            channel: event.channel as u8,
+            note: event.key as u8,
+            velocity: event.velocity as f32,
+        });
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap_sys::ext::params::CLAP_PARAM_IS_MODULATABLE;
+    use std::path::PathBuf;
+
+    use crate::test_util;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const MAX_BLOCK_SIZE: usize = 512;
+    /// Includes single samples, odd sizes, and sizes that don't evenly divide the input's length.
+    const BLOCK_SIZES: [usize; 5] = [1, 7, 64, 333, MAX_BLOCK_SIZE];
+    const INPUT_LEN: usize = 36000;
+    /// The first burst starts after this many samples of noise, so the spectral gate can learn the
+    /// noise on its own.
+    const BURST_INTERVAL: usize = 9000;
+
+    /// The golden files are written instead of compared when this environment variable is set. A
+    /// missing golden file fails the test otherwise.
+    const BLESS_ENV: &str = "NOISEG8_BLESS";
+
+    /// Noise at -60 dBFS RMS with a decaying 150 Hz burst at -12 dBFS every [`BURST_INTERVAL`]
+    /// samples. Every channel gets its own noise.
+    fn test_input(num_channels: usize) -> Audio {
+        let mut noise = test_util::noise();
+        let noise_amplitude = nih_plug::util::db_to_gain(-60.0) * 3.0f32.sqrt();
+        let burst_amplitude = nih_plug::util::db_to_gain(-12.0);
+
+        let mut channels = vec![Vec::with_capacity(INPUT_LEN); num_channels];
+        for sample_idx in 0..INPUT_LEN {
+            let burst_time = (sample_idx % BURST_INTERVAL) as f32 / SAMPLE_RATE;
+            let burst = if sample_idx >= BURST_INTERVAL {
+                (std::f32::consts::TAU * 150.0 * burst_time).sin() * (-burst_time * 20.0).exp()
+            } else {
+                0.0
+            };
+
+            for channel in &mut channels {
+                let noise = noise.next().unwrap();
+                channel.push(noise * noise_amplitude + burst * burst_amplitude);
+            }
+        }
+
+        Audio {
+            sample_rate: SAMPLE_RATE,
+            channels,
+        }
+    }
+
+    /// Stream the test input through a WAV file, the same way the renderer is used outside of the
+    /// tests.
+    fn test_input_wav(name: &str, num_channels: usize) -> Audio {
+        let path = std::env::temp_dir().join(format!("noiseg8-{name}-input.wav"));
+        test_input(num_channels).write_wav(&path).unwrap();
+
+        Audio::read_wav(&path).unwrap()
+    }
+
+    /// A single full scale sample at `impulse_idx` on every channel.
+    fn impulse(num_channels: usize, len: usize, impulse_idx: usize) -> Audio {
+        let mut channel = vec![0.0; len];
+        channel[impulse_idx] = 1.0;
+
+        Audio {
+            sample_rate: SAMPLE_RATE,
+            channels: vec![channel; num_channels],
+        }
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("golden")
+            .join(format!("{name}.wav"))
+    }
+
+    fn new_renderer(num_channels: usize) -> Renderer {
+        let buffer_config = BufferConfig {
+            sample_rate: SAMPLE_RATE,
+            min_buffer_size: None,
+            max_buffer_size: MAX_BLOCK_SIZE as u32,
+            process_mode: ProcessMode::Offline,
+        };
+
+        Renderer::new(&buffer_config, num_channels).unwrap()
+    }
+
+    fn render(input: &Audio, block_size: usize, automation: &[Automation]) -> Audio {
+        new_renderer(input.channels.len()).render(input, block_size, automation).unwrap()
+    }
+
+    fn assert_bit_identical(actual: &Audio, expected: &Audio, context: &str) {
+        assert_eq!(actual.channels.len(), expected.channels.len(), "{context}: channel count");
+        for (channel_idx, (actual, expected)) in actual.channels
+            .iter()
+            .zip(&expected.channels)
+            .enumerate() {
+            assert_eq!(actual.len(), expected.len(), "{context}: length");
+            let mismatch = actual
+                .iter()
+                .zip(expected)
+                .position(|(actual, expected)| actual.to_bits() != expected.to_bits());
+            if let Some(sample_idx) = mismatch {
+                panic!(
+                    "{context}: channel {channel_idx} differs at sample {sample_idx} ({} != {})",
+                    actual[sample_idx],
+                    expected[sample_idx]
+                );
+            }
+        }
+    }
+
+    /// Render the input at every block size in [`BLOCK_SIZES`], check that the outputs are bit
+    /// identical, and compare them to the golden file.
+    fn assert_golden(name: &str, num_channels: usize, automation: &[Automation]) {
+        let input = test_input_wav(name, num_channels);
+        let reference = render(&input, MAX_BLOCK_SIZE, automation);
+        for block_size in BLOCK_SIZES {
+            let output = render(&input, block_size, automation);
+            assert_bit_identical(&output, &reference, &format!("{name}, {block_size} samples"));
+        }
+
+        let golden_path = golden_path(name);
+        if std::env::var_os(BLESS_ENV).is_some() {
+            std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+            reference.write_wav(&golden_path).unwrap();
+            eprintln!("Wrote {}", golden_path.display());
+        } else if !golden_path.exists() {
+            panic!(
+                "{} does not exist, run the tests with {BLESS_ENV}=1 to create it",
+                golden_path.display()
+            );
+        } else {
+            let golden = Audio::read_wav(&golden_path).unwrap();
+            assert_bit_identical(&reference, &golden, &format!("{name}, golden file"));
+        }
+    }
+
+    /// Hosts like Bitwig only draw their modulation display for parameters that are marked as
+    /// modulatable, and the plugin reads the modulated values.
+    #[test]
+    fn automatable_params_are_modulatable() {
+        let renderer = new_renderer(2);
+        for (param_id, param_info) in &renderer.params {
+            let (param_flags, info_flags) = unsafe {
+                let params = renderer.extension::<clap_plugin_params>(CLAP_EXT_PARAMS);
+                let num_params = ((*params).count.unwrap())(renderer.plugin);
+                let mut info: clap_param_info = std::mem::zeroed();
+                let found = (0..num_params).any(|param_idx| {
+                    ((*params).get_info.unwrap())(renderer.plugin, param_idx, &mut info) &&
+                        info.id == param_info.clap_id
+                });
+                assert!(found, "{param_id}");
+
+                (param_info.param_ptr.flags(), info.flags)
+            };
+
+            assert_eq!(
+                info_flags & CLAP_PARAM_IS_MODULATABLE != 0,
+                !param_flags.contains(ParamFlags::NON_AUTOMATABLE),
+                "{param_id}"
+            );
+        }
+    }
+
+    #[test]
+    fn gate_with_automation() {
+        assert_golden("gate", 2, &[
+            Automation::new(0, "hold", 20.0),
+            Automation::new(6000, "lookahead", 5.0),
+            Automation::new(12000, "threshold", -30.0),
+            Automation::new(20000, "mix", 0.5),
+            Automation::new(20000, "output_gain", nih_plug::util::db_to_gain(-6.0)),
+            Automation::new(27000, "bypass", 1.0),
+            Automation::new(31000, "bypass", 0.0),
+        ]);
+    }
+
+    #[test]
+    fn expander_mono() {
+        assert_golden("expander", 1, &[
+            Automation::new(0, "mode", 1.0),
+            Automation::new(0, "ratio", 4.0),
+            Automation::new(15000, "knee", 12.0),
+        ]);
+    }
+
+    #[test]
+    fn multiband_with_automation() {
+        assert_golden("multiband", 2, &[
+            Automation::new(0, "mode", 2.0),
+            Automation::new(0, "num_bands", 4.0),
+            Automation::new(10000, "threshold_2", -50.0),
+            Automation::new(25000, "xover_1", 400.0),
+        ]);
+    }
+
+    #[test]
+    fn spectral_with_learning() {
+        assert_golden("spectral", 2, &[
+            Automation::new(0, "mode", 3.0),
+            Automation::new(0, "spectral_fft", 1.0),
+            Automation::new(0, "spectral_learn", 1.0),
+            Automation::new(BURST_INTERVAL - 1000, "spectral_learn", 0.0),
+        ]);
+    }
+
+    #[test]
+    fn tempo_synced_hold_and_release() {
+        let input = test_input(1);
+        let render_at_tempo = |tempo: Option<f64>, automation: &[Automation]| {
+            let mut renderer = new_renderer(1);
+            renderer.set_tempo(tempo);
+            let mut automation = automation.to_vec();
+            // The bursts need to drop below the threshold well before the next burst starts
+            automation.push(Automation::new(0, "threshold", -20.0));
+
+            renderer.render(&input, MAX_BLOCK_SIZE, &automation).unwrap()
+        };
+        let synced = [
+            Automation::new(0, "tempo_sync", 1.0),
+            // 1/32 and 1/16
+            Automation::new(0, "hold_note", 13.0),
+            Automation::new(0, "release_note", 11.0),
+        ];
+        let short_times = [Automation::new(0, "hold", 5.0), Automation::new(0, "release", 5.0)];
+        let long_times = [Automation::new(0, "hold", 500.0), Automation::new(0, "release", 2000.0)];
+
+        // With a tempo the millisecond times are not used, but the tempo is
+        let synced_short = render_at_tempo(Some(120.0), &[&synced[..], &short_times].concat());
+        let synced_long = render_at_tempo(Some(120.0), &[&synced[..], &long_times].concat());
+        assert_bit_identical(&synced_short, &synced_long, "synced at 120 BPM");
+        let synced_slow = render_at_tempo(Some(60.0), &[&synced[..], &short_times].concat());
+        assert_ne!(synced_slow, synced_short, "the tempo is ignored");
+
+        // Without a tempo the millisecond times are used instead
+        let fallback = render_at_tempo(None, &[&synced[..], &long_times].concat());
+        let unsynced = render_at_tempo(None, &long_times);
+        assert_bit_identical(&fallback, &unsynced, "synced without a tempo");
+        assert_ne!(fallback, synced_long, "the millisecond times are ignored");
+    }
+
+    #[test]
+    fn lookahead_delays_by_the_reported_latency() {
+        let input = impulse(2, 4096, 1000);
+        for key_listen in [0.0, 1.0] {
+            let mut renderer = new_renderer(2);
+            let output = renderer
+                .render(&input, MAX_BLOCK_SIZE, &[
+                    Automation::new(0, "lookahead", 5.0),
+                    // Without any attenuation the gate doesn't change the signal
+                    Automation::new(0, "range", 0.0),
+                    Automation::new(0, "key_listen", key_listen),
+                ])
+                .unwrap();
+
+            let latency_samples = renderer.latency_samples() as usize;
+            assert_eq!(latency_samples, 240);
+            let expected = impulse(2, 4096, 1000 + latency_samples);
+            assert_bit_identical(&output, &expected, &format!("key listen {key_listen}"));
+        }
+    }
+
+    #[test]
+    fn key_listen_only_changes_the_output_while_enabled() {
+        const LISTEN_START: usize = 12000;
+        const LISTEN_END: usize = 24000;
+
+        let input = test_input(2);
+        let key_filter = [
+            Automation::new(0, "key_hpf", 1.0),
+            Automation::new(0, "key_hpf_freq", 1000.0),
+        ];
+        let key_listen = [
+            Automation::new(LISTEN_START, "key_listen", 1.0),
+            Automation::new(LISTEN_END, "key_listen", 0.0),
+        ];
+        let gated = render(&input, MAX_BLOCK_SIZE, &key_filter);
+        let listening = render(&input, MAX_BLOCK_SIZE, &[&key_filter[..], &key_listen].concat());
+
+        for ((gated, listening), input) in gated.channels
+            .iter()
+            .zip(&listening.channels)
+            .zip(&input.channels) {
+            for sample_idx in (0..LISTEN_START).chain(LISTEN_END..INPUT_LEN) {
+                assert_eq!(
+                    listening[sample_idx].to_bits(),
+                    gated[sample_idx].to_bits(),
+                    "sample {sample_idx}"
+                );
+            }
+
+            // The 150 Hz bursts are mostly filtered out of the key signal
+            let listen_range = LISTEN_START..LISTEN_END;
+            assert_ne!(listening[listen_range.clone()], gated[listen_range.clone()]);
+            assert_ne!(listening[listen_range.clone()], input[listen_range]);
+        }
+    }
+
+    #[test]
+    fn duck_attenuates_by_the_range_while_keyed() {
+        const KEY_START: usize = 12000;
+        const KEY_END: usize = 24000;
+
+        let sine = |frequency: f32, amplitude: f32, sample_idx: usize| {
+            (std::f32::consts::TAU * frequency * sample_idx as f32 / SAMPLE_RATE).sin() * amplitude
+        };
+        let input = Audio {
+            sample_rate: SAMPLE_RATE,
+            channels: vec![
+                (0..INPUT_LEN).map(|sample_idx| sine(440.0, 0.25, sample_idx)).collect();
+                2
+            ],
+        };
+        let key = Audio {
+            sample_rate: SAMPLE_RATE,
+            channels: vec![
+                (0..INPUT_LEN)
+                    .map(|sample_idx| {
+                        if (KEY_START..KEY_END).contains(&sample_idx) {
+                            sine(1000.0, 0.5, sample_idx)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect();
+                2
+            ],
+        };
+
+        let output = new_renderer(2)
+            .render_with_sidechain(&input, Some(&key), MAX_BLOCK_SIZE, &[
+                Automation::new(0, "duck", 1.0),
+                Automation::new(0, "key_source", 1.0),
+                Automation::new(0, "range", -20.0),
+            ])
+            .unwrap();
+
+        // The default attack is 1 ms, and the default hold and release times add up to 150 ms
+        let ducked = KEY_START + 480..KEY_END;
+        let unity = (0..KEY_START).chain(KEY_END + 9600..INPUT_LEN);
+        let expected_gains = ducked
+            .map(|sample_idx| (sample_idx, nih_plug::util::db_to_gain(-20.0)))
+            .chain(unity.map(|sample_idx| (sample_idx, 1.0)));
+        for (sample_idx, expected_gain) in expected_gains {
+            for (output_channel, input_channel) in output.channels.iter().zip(&input.channels) {
+                let (output, input) = (output_channel[sample_idx], input_channel[sample_idx]);
+                assert!(
+                    (output - input * expected_gain).abs() <= 1e-5,
+                    "sample {sample_idx}: {output} != {input} * {expected_gain}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn spectral_all_open_profile_is_transparent() {
+        // Learning digital silence results in a profile of zeroes, so every bin stays open
+        let silence_len = 8192;
+        let mut input = test_input(2);
+        for channel in &mut input.channels {
+            channel[..silence_len].fill(0.0);
+        }
+
+        let mut renderer = new_renderer(2);
+        let output = renderer
+            .render(&input, MAX_BLOCK_SIZE, &[
+                Automation::new(0, "mode", 3.0),
+                Automation::new(0, "spectral_learn", 1.0),
+                Automation::new(silence_len, "spectral_learn", 0.0),
+            ])
+            .unwrap();
+
+        let latency_samples = renderer.latency_samples() as usize;
+        for (output_channel, input_channel) in output.channels.iter().zip(&input.channels) {
+            for sample_idx in silence_len..INPUT_LEN - latency_samples {
+                let (output, input) = (
+                    output_channel[sample_idx + latency_samples],
+                    input_channel[sample_idx],
+                );
+                assert!((output - input).abs() <= 1e-5, "sample {sample_idx}: {output} != {input}");
+            }
+        }
+    }
+
+    #[test]
+    fn zero_mix_is_delayed_dry_input() {
+        let input = test_input(2);
+        // The mix parameter is smoothed over 50 ms
+        let smoothing_samples = 2400;
+        let skip_smoothing = |audio: &Audio| Audio {
+            sample_rate: audio.sample_rate,
+            channels: audio.channels
+                .iter()
+                .map(|channel| channel[smoothing_samples..].to_vec())
+                .collect(),
+        };
+
+        // Multiband mode phase-matches the dry signal, but not at a mix of 0
+        for (mode, mode_name) in [(0.0, "gate"), (2.0, "multiband")] {
+            let mut renderer = new_renderer(2);
+            let output = renderer
+                .render(&input, MAX_BLOCK_SIZE, &[
+                    Automation::new(0, "mode", mode),
+                    Automation::new(0, "lookahead", 5.0),
+                    Automation::new(0, "mix", 0.0),
+                    // None of this should end up in the output
+                    Automation::new(0, "gain", nih_plug::util::db_to_gain(12.0)),
+                    Automation::new(0, "threshold", -20.0),
+                ])
+                .unwrap();
+
+            let latency_samples = renderer.latency_samples() as usize;
+            assert_eq!(latency_samples, 240, "{mode_name}");
+            let mut expected = input.clone();
+            for channel in &mut expected.channels {
+                channel.rotate_right(latency_samples);
+                channel[..latency_samples].fill(0.0);
+            }
+            assert_bit_identical(
+                &skip_smoothing(&output),
+                &skip_smoothing(&expected),
+                &format!("{mode_name}, zero mix")
+            );
+        }
+    }
+
+    #[test]
+    fn mid_side_is_transparent() {
+        let input = test_input(2);
+        let output = render(&input, MAX_BLOCK_SIZE, &[
+            Automation::new(0, "channel_mode", 1.0),
+            // The gate is always fully open without any attenuation
+            Automation::new(0, "range", 0.0),
+        ]);
+
+        for (output_channel, input_channel) in output.channels.iter().zip(&input.channels) {
+            for (sample_idx, (output, input)) in output_channel
+                .iter()
+                .zip(input_channel)
+                .enumerate() {
+                assert!(
+                    (output - input).abs() <= f32::EPSILON * 2.0,
+                    "sample {sample_idx}: {output} != {input}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn midi_output_note_on_is_sample_accurate() {
+        const HIT_START: usize = 12001;
+        const HIT_END: usize = 16801;
+
+        let input = Audio {
+            sample_rate: SAMPLE_RATE,
+            channels: vec![
+                (0..INPUT_LEN)
+                    .map(|sample_idx| {
+                        if (HIT_START..HIT_END).contains(&sample_idx) {
+                            let time = (sample_idx - HIT_START) as f32 / SAMPLE_RATE;
+                            (std::f32::consts::TAU * 1000.0 * time).cos() * 0.5
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect();
+                2
+            ],
+        };
+
+        // Without lookahead the NoteOn is sent on the sample the gate opens, and with lookahead
+        // it's sent when the hit reaches the output
+        for (lookahead_ms, lookahead_samples) in [(0.0, 0), (5.0, 240)] {
+            for block_size in BLOCK_SIZES {
+                let context = format!("{lookahead_ms} ms lookahead, {block_size} samples");
+                let mut renderer = new_renderer(2);
+                renderer
+                    .render(&input, block_size, &[
+                        Automation::new(0, "midi_out", 1.0),
+                        Automation::new(0, "lookahead", lookahead_ms),
+                    ])
+                    .unwrap();
+
+                let notes = renderer.notes();
+                assert_eq!(notes.len(), 2, "{context}: {notes:?}");
+                assert!(notes[0].note_on, "{context}");
+                assert_eq!(notes[0].sample, HIT_START + lookahead_samples, "{context}");
+                assert!(!notes[1].note_on, "{context}");
+                assert!(notes[1].sample > HIT_END, "{context}");
+            }
+        }
+    }
+}
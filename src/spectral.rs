@@ -0,0 +1,430 @@
+//! A spectral noise gate that gates every frequency bin against a learned noise profile.
+
+use nih_plug::prelude::{ util, Buffer };
+use nih_plug::util::window::multiply_with_window;
+use realfft::num_complex::Complex32;
+use realfft::{ ComplexToReal, RealFftPlanner, RealToComplex };
+use serde::{ Deserialize, Deserializer, Serialize };
+use std::sync::Arc;
+
+/// The FFT sizes supported by the spectral gate.
+pub const FFT_SIZES: [usize; 4] = [512, 1024, 2048, 4096];
+/// The largest supported FFT size. All buffers are allocated for this size.
+pub const MAX_FFT_SIZE: usize = 4096;
+/// The number of frequency bins at [`MAX_FFT_SIZE`].
+const MAX_NUM_BINS: usize = MAX_FFT_SIZE / 2 + 1;
+/// The number of STFT frames that overlap every sample.
+const OVERLAP_TIMES: usize = 4;
+
+/// The average magnitude of every frequency bin captured while learning. Magnitudes are normalized
+/// so a sine wave's bin has roughly the sine's amplitude, regardless of the FFT size.
+///
+/// Default and deserialized profiles have room for [`MAX_FFT_SIZE`]'s bins, so learning or copying
+/// a profile never allocates on the audio thread.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoiseProfile {
+    /// The FFT size the profile was captured with. Zero if nothing has been captured yet.
+    pub fft_size: usize,
+    /// The average magnitude of every bin, `fft_size / 2 + 1` values.
+    #[serde(deserialize_with = "deserialize_magnitudes")]
+    pub magnitudes: Vec<f32>,
+}
+
+/// Gates every frequency bin of the input on its own. A bin passes when its magnitude exceeds the
+/// noise profile's magnitude for that bin by the threshold offset, and is attenuated by the
+/// reduction amount otherwise. The resulting gains are smoothed across neighbouring bins and over
+/// time to avoid musical noise.
+pub struct SpectralGate {
+    stft: util::StftHelper,
+    /// The FFT plans and windows for every size in [`FFT_SIZES`], in the same order.
+    plans: Vec<FftPlan>,
+    /// The index in `plans` for the current FFT size.
+    plan_idx: usize,
+    /// Scratch space for the current frame's spectrum.
+    spectrum: Vec<Complex32>,
+
+    /// The profile used to derive the per-bin thresholds.
+    noise_profile: NoiseProfile,
+    /// The threshold for every bin of the current FFT size, as a normalized magnitude.
+    thresholds: Vec<f32>,
+    /// The unsmoothed gain for every bin of the current frame.
+    target_gains: Vec<f32>,
+    /// The smoothed gain for every bin, one vector per channel.
+    gains: Vec<Vec<f32>>,
+    /// The average of `gains` for every channel after the last frame. Only used for display
+    /// purposes.
+    average_gains: Vec<f32>,
+
+    /// The gain applied to bins below their threshold.
+    reduction_gain: f32,
+    /// The weight the previous gain gets on every frame.
+    time_smoothing_weight: f32,
+    /// The number of bins on either side of a bin that are averaged into its gain.
+    frequency_smoothing_bins: usize,
+
+    /// Whether the input is being analyzed for a new noise profile. The audio passes through
+    /// unchanged while learning.
+    learning: bool,
+    /// The sum of every bin's magnitude over all frames analyzed while learning.
+    learn_sums: Vec<f32>,
+    /// The number of frames summed in `learn_sums`, counting every channel separately.
+    learn_frames: u32,
+}
+
+/// The FFT plans and the analysis/synthesis window for a single FFT size.
+struct FftPlan {
+    fft_size: usize,
+    r2c_plan: Arc<dyn RealToComplex<f32>>,
+    c2r_plan: Arc<dyn ComplexToReal<f32>>,
+    window: Vec<f32>,
+}
+
+impl Default for NoiseProfile {
+    fn default() -> Self {
+        Self {
+            fft_size: 0,
+            magnitudes: Vec::with_capacity(MAX_NUM_BINS),
+        }
+    }
+}
+
+impl NoiseProfile {
+    /// Whether a profile has been captured.
+    pub fn is_empty(&self) -> bool {
+        self.magnitudes.is_empty()
+    }
+
+    /// Replace this profile with a copy of `other`, reusing the existing allocation. This doesn't
+    /// allocate as long as this profile was created through `default()` or deserialized.
+    pub fn copy_from(&mut self, other: &NoiseProfile) {
+        self.fft_size = other.fft_size;
+        self.magnitudes.clear();
+        self.magnitudes.extend_from_slice(&other.magnitudes);
+    }
+
+    /// The profile's magnitude for a bin at a possibly different FFT size, linearly interpolated
+    /// between the captured bins. Broadband noise spreads over more bins at larger FFT sizes, so
+    /// the magnitude is also scaled to match the new bin width. Returns `None` if the profile is
+    /// empty.
+    fn magnitude(&self, bin: usize, fft_size: usize) -> Option<f32> {
+        let last_bin = self.magnitudes.len().checked_sub(1)?;
+        let position = ((bin * self.fft_size) as f32 / fft_size as f32).min(last_bin as f32);
+        let lower_bin = position.floor() as usize;
+        let upper_bin = (lower_bin + 1).min(last_bin);
+        let t = position - lower_bin as f32;
+        let lower_magnitude = self.magnitudes[lower_bin];
+        let magnitude = lower_magnitude + (self.magnitudes[upper_bin] - lower_magnitude) * t;
+
+        Some(magnitude * (self.fft_size as f32 / fft_size as f32).sqrt())
+    }
+}
+
+impl SpectralGate {
+    /// Create a spectral gate for `num_channels` channels. This allocates all buffers and FFT plans
+    /// up front.
+    pub fn new(num_channels: usize) -> Self {
+        let mut planner = RealFftPlanner::new();
+        let plans = FFT_SIZES.iter()
+            .map(|&fft_size| FftPlan {
+                fft_size,
+                r2c_plan: planner.plan_fft_forward(fft_size),
+                c2r_plan: planner.plan_fft_inverse(fft_size),
+                window: util::window::hann(fft_size),
+            })
+            .collect();
+
+        let mut spectral_gate = Self {
+            stft: util::StftHelper::new(num_channels, MAX_FFT_SIZE, 0),
+            plans,
+            plan_idx: 0,
+            spectrum: vec![Complex32::default(); MAX_NUM_BINS],
+
+            // This already has room for the largest FFT size's profile
+            noise_profile: NoiseProfile::default(),
+            thresholds: vec![0.0; MAX_NUM_BINS],
+            target_gains: vec![1.0; MAX_NUM_BINS],
+            gains: vec![vec![1.0; MAX_NUM_BINS]; num_channels],
+            average_gains: vec![1.0; num_channels],
+
+            reduction_gain: 1.0,
+            time_smoothing_weight: 0.0,
+            frequency_smoothing_bins: 0,
+
+            learning: false,
+            learn_sums: vec![0.0; MAX_NUM_BINS],
+            learn_frames: 0,
+        };
+        spectral_gate.set_fft_size(FFT_SIZES[0]);
+
+        spectral_gate
+    }
+
+    /// The current FFT size.
+    pub fn fft_size(&self) -> usize {
+        self.plans[self.plan_idx].fft_size
+    }
+
+    /// The STFT's latency in samples. This is equal to the FFT size.
+    pub fn latency_samples(&self) -> u32 {
+        self.stft.latency_samples()
+    }
+
+    /// Change the FFT size. The size must be one of [`FFT_SIZES`], other sizes are ignored. This
+    /// clears the gate's state, including a noise profile that is still being learned, if the size
+    /// changes.
+    pub fn set_fft_size(&mut self, fft_size: usize) {
+        let Some(plan_idx) = self.plans.iter().position(|plan| plan.fft_size == fft_size) else {
+            return;
+        };
+        if plan_idx == self.plan_idx && self.stft.latency_samples() as usize == fft_size {
+            return;
+        }
+
+        self.plan_idx = plan_idx;
+        self.reset();
+    }
+
+    /// Set the gating parameters. The threshold for every bin is the noise profile's magnitude
+    /// raised by `threshold_offset_db`. Without a noise profile every bin uses `fallback_threshold`
+    /// instead. Bins below their threshold are attenuated by `reduction_db`. The time smoothing is
+    /// the time constant for the per-bin gains, and the frequency smoothing is the number of
+    /// neighbouring bins on either side that get averaged into a bin's gain.
+    pub fn set_parameters(
+        &mut self,
+        sample_rate: f32,
+        threshold_offset_db: f32,
+        fallback_threshold: f32,
+        reduction_db: f32,
+        time_smoothing_ms: f32,
+        frequency_smoothing_bins: usize
+    ) {
+        let fft_size = self.fft_size();
+        let threshold_offset = util::db_to_gain(threshold_offset_db);
+        for (bin, threshold) in self.thresholds[..fft_size / 2 + 1].iter_mut().enumerate() {
+            *threshold = match self.noise_profile.magnitude(bin, fft_size) {
+                Some(magnitude) => magnitude * threshold_offset,
+                None => fallback_threshold,
+            };
+        }
+
+        self.reduction_gain = util::db_to_gain(reduction_db);
+        let hop_size = (fft_size / OVERLAP_TIMES) as f32;
+        let time_smoothing_samples = (sample_rate * time_smoothing_ms) / 1000.0;
+        self.time_smoothing_weight = if time_smoothing_samples > 0.0 {
+            (-hop_size / time_smoothing_samples).exp()
+        } else {
+            0.0
+        };
+        self.frequency_smoothing_bins = frequency_smoothing_bins;
+    }
+
+    /// The noise profile currently used for the thresholds.
+    pub fn noise_profile(&self) -> &NoiseProfile {
+        &self.noise_profile
+    }
+
+    /// Replace the noise profile, for instance after restoring the plugin's state.
+    pub fn set_noise_profile(&mut self, noise_profile: &NoiseProfile) {
+        self.noise_profile.copy_from(noise_profile);
+    }
+
+    /// Whether the input is currently being analyzed for a new noise profile.
+    pub fn is_learning(&self) -> bool {
+        self.learning
+    }
+
+    /// Start analyzing the input for a new noise profile. The audio passes through unchanged until
+    /// [`finish_learning()`][Self::finish_learning()] is called.
+    pub fn start_learning(&mut self) {
+        self.learning = true;
+        self.learn_sums.fill(0.0);
+        self.learn_frames = 0;
+    }
+
+    /// Stop learning and replace the noise profile with the average magnitudes analyzed since
+    /// learning started. Returns `false` and keeps the old profile if no frames were analyzed.
+    pub fn finish_learning(&mut self) -> bool {
+        self.learning = false;
+        if self.learn_frames == 0 {
+            return false;
+        }
+
+        // The profile's capacity is reserved up front, so this doesn't allocate
+        let fft_size = self.fft_size();
+        let num_frames = self.learn_frames as f32;
+        self.noise_profile.fft_size = fft_size;
+        self.noise_profile.magnitudes.clear();
+        self.noise_profile.magnitudes.extend(
+            self.learn_sums[..fft_size / 2 + 1].iter().map(|sum| sum / num_frames)
+        );
+
+        true
+    }
+
+    /// The average gain over all bins for a channel, for display purposes.
+    pub fn average_gain(&self, channel_idx: usize) -> f32 {
+        self.average_gains.get(channel_idx).copied().unwrap_or(1.0)
+    }
+
+    /// Clear the STFT buffers and open every bin. This does not affect the noise profile.
+    pub fn reset(&mut self) {
+        self.stft.set_block_size(self.fft_size());
+        for gains in &mut self.gains {
+            gains.fill(1.0);
+        }
+        self.average_gains.fill(1.0);
+        if self.learning {
+            self.start_learning();
+        }
+    }
+
+    /// Gate the buffer in place. The output is delayed by
+    /// [`latency_samples()`][Self::latency_samples()].
+    pub fn process(&mut self, buffer: &mut Buffer) {
+        let plan = &self.plans[self.plan_idx];
+        let fft_size = plan.fft_size;
+        let num_bins = fft_size / 2 + 1;
+        // The window is applied twice with `OVERLAP_TIMES` overlapping frames, and the inverse FFT
+        // is not normalized
+        let gain_compensation = ((OVERLAP_TIMES as f32 / 4.0) * 1.5).recip() / fft_size as f32;
+        // A Hann window's sum is half its length, so this turns a sine's bin into its amplitude
+        let magnitude_scale = 2.0 / fft_size as f32;
+
+        let spectrum = &mut self.spectrum[..num_bins];
+        let thresholds = &self.thresholds[..num_bins];
+        let target_gains = &mut self.target_gains[..num_bins];
+        let learn_sums = &mut self.learn_sums[..num_bins];
+        self.stft.process_overlap_add(buffer, OVERLAP_TIMES, |channel_idx, real_fft_buffer| {
+            multiply_with_window(real_fft_buffer, &plan.window);
+            plan.r2c_plan.process_with_scratch(real_fft_buffer, spectrum, &mut []).unwrap();
+
+            if self.learning {
+                for (sum, bin) in learn_sums.iter_mut().zip(spectrum.iter()) {
+                    *sum += bin.norm() * magnitude_scale;
+                }
+                self.learn_frames += 1;
+            } else {
+                for ((target_gain, bin), threshold) in target_gains
+                    .iter_mut()
+                    .zip(spectrum.iter())
+                    .zip(thresholds) {
+                    *target_gain = if bin.norm() * magnitude_scale >= *threshold {
+                        1.0
+                    } else {
+                        self.reduction_gain
+                    };
+                }
+
+                let gains = &mut self.gains[channel_idx][..num_bins];
+                smooth_gains(
+                    gains,
+                    target_gains,
+                    self.frequency_smoothing_bins,
+                    self.time_smoothing_weight
+                );
+                for (bin, gain) in spectrum.iter_mut().zip(gains.iter()) {
+                    *bin *= *gain;
+                }
+                self.average_gains[channel_idx] = gains.iter().sum::<f32>() / num_bins as f32;
+            }
+
+            for bin in spectrum.iter_mut() {
+                *bin *= gain_compensation;
+            }
+            plan.c2r_plan.process_with_scratch(spectrum, real_fft_buffer, &mut []).unwrap();
+            multiply_with_window(real_fft_buffer, &plan.window);
+        });
+    }
+}
+
+/// Move `gains` towards `target_gains` averaged over `frequency_smoothing_bins` bins on either
+/// side, using `time_smoothing_weight` as the weight for the previous gain.
+fn smooth_gains(
+    gains: &mut [f32],
+    target_gains: &[f32],
+    frequency_smoothing_bins: usize,
+    time_smoothing_weight: f32
+) {
+    let num_bins = target_gains.len();
+    if num_bins == 0 {
+        return;
+    }
+
+    // A moving sum over the bins in `[bin - frequency_smoothing_bins, bin +
+    // frequency_smoothing_bins]`, clamped to the spectrum's edges
+    let mut window_sum: f32 = target_gains[..(frequency_smoothing_bins + 1).min(num_bins)]
+        .iter()
+        .sum();
+    for (bin, gain) in gains.iter_mut().enumerate() {
+        let window_start = bin.saturating_sub(frequency_smoothing_bins);
+        let window_end = (bin + frequency_smoothing_bins).min(num_bins - 1);
+        let target_gain = window_sum / (window_end - window_start + 1) as f32;
+        *gain = target_gain + (*gain - target_gain) * time_smoothing_weight;
+
+        if bin + frequency_smoothing_bins + 1 < num_bins {
+            window_sum += target_gains[bin + frequency_smoothing_bins + 1];
+        }
+        if bin >= frequency_smoothing_bins {
+            window_sum -= target_gains[bin - frequency_smoothing_bins];
+        }
+    }
+}
+
+/// Deserialize a noise profile's magnitudes with room for [`MAX_FFT_SIZE`]'s bins, see
+/// [`NoiseProfile`].
+fn deserialize_magnitudes<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Vec<f32>, D::Error> {
+    let mut magnitudes = Vec::deserialize(deserializer)?;
+    magnitudes.reserve(MAX_NUM_BINS.saturating_sub(magnitudes.len()));
+
+    Ok(magnitudes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_capacity_is_reserved() {
+        assert!(NoiseProfile::default().magnitudes.capacity() >= MAX_NUM_BINS);
+
+        let noise_profile: NoiseProfile = serde_json::from_str(
+            r#"{"fft_size":4,"magnitudes":[0.1,0.2,0.3]}"#
+        ).unwrap();
+        assert_eq!(noise_profile.magnitudes, [0.1, 0.2, 0.3]);
+        assert!(noise_profile.magnitudes.capacity() >= MAX_NUM_BINS);
+    }
+
+    #[test]
+    fn smooth_gains_without_smoothing() {
+        let mut gains = [0.5; 4];
+        smooth_gains(&mut gains, &[0.0, 1.0, 0.25, 1.0], 0, 0.0);
+        assert_eq!(gains, [0.0, 1.0, 0.25, 1.0]);
+    }
+
+    #[test]
+    fn smooth_gains_clamps_the_window_to_the_edges() {
+        let mut gains = [0.0; 5];
+        smooth_gains(&mut gains, &[1.0, 0.0, 0.0, 0.0, 1.0], 1, 0.0);
+        // The first and last bins only average two bins
+        assert_eq!(gains, [0.5, 1.0 / 3.0, 0.0, 1.0 / 3.0, 0.5]);
+
+        // A window wider than the spectrum averages every bin
+        let mut gains = [0.0; 3];
+        smooth_gains(&mut gains, &[1.0, 0.0, 0.5], 10, 0.0);
+        assert_eq!(gains, [0.5; 3]);
+    }
+
+    #[test]
+    fn smooth_gains_over_time() {
+        let mut gains = [1.0; 3];
+        smooth_gains(&mut gains, &[0.0; 3], 1, 0.75);
+        assert_eq!(gains, [0.75; 3]);
+    }
+
+    #[test]
+    fn smooth_gains_without_bins() {
+        smooth_gains(&mut [], &[], 4, 0.5);
+    }
+}
@@ -0,0 +1,59 @@
+//! Mid/side encoding and decoding.
+
+/// Convert a left/right sample pair to a mid/side pair. Mid is the average of the two channels, and
+/// side is half their difference.
+pub fn mid_side_encode(left: f32, right: f32) -> (f32, f32) {
+    ((left + right) * 0.5, (left - right) * 0.5)
+}
+
+/// The inverse of [`mid_side_encode()`].
+pub fn mid_side_decode(mid: f32, side: f32) -> (f32, f32) {
+    (mid + side, mid - side)
+}
+
+/// Encode the first two samples yielded by an iterator to mid/side in place. Does nothing if the
+/// iterator yields fewer than two samples.
+pub fn mid_side_encode_in_place<'a>(mut samples: impl Iterator<Item = &'a mut f32>) {
+    if let (Some(left), Some(right)) = (samples.next(), samples.next()) {
+        (*left, *right) = mid_side_encode(*left, *right);
+    }
+}
+
+/// Decode the first two samples yielded by an iterator from mid/side back to left/right in place.
+/// Does nothing if the iterator yields fewer than two samples.
+pub fn mid_side_decode_in_place<'a>(mut samples: impl Iterator<Item = &'a mut f32>) {
+    if let (Some(mid), Some(side)) = (samples.next(), samples.next()) {
+        (*mid, *side) = mid_side_decode(*mid, *side);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn round_trip() {
+        let mut noise = test_util::noise();
+        for _ in 0..10000 {
+            let left = noise.next().unwrap();
+            let right = noise.next().unwrap();
+            let mut samples = [left, right];
+            mid_side_encode_in_place(samples.iter_mut());
+            mid_side_decode_in_place(samples.iter_mut());
+
+            // Every step rounds at most once, so the error stays within a few ULPs of the inputs
+            let tolerance = left.abs().max(right.abs()) * f32::EPSILON * 2.0;
+            assert!((samples[0] - left).abs() <= tolerance, "{left} -> {}", samples[0]);
+            assert!((samples[1] - right).abs() <= tolerance, "{right} -> {}", samples[1]);
+        }
+    }
+
+    #[test]
+    fn single_channel_is_untouched() {
+        let mut samples = [0.5];
+        mid_side_encode_in_place(samples.iter_mut());
+        mid_side_decode_in_place(samples.iter_mut());
+        assert_eq!(samples, [0.5]);
+    }
+}
@@ -0,0 +1,349 @@
+//! Lock-free telemetry shared between the audio thread and the editor.
+
+use nih_plug::prelude::AtomicF32;
+use std::sync::atomic::{ AtomicBool, AtomicU8, AtomicUsize, Ordering };
+
+use crate::gate::GateStage;
+use crate::meter::PeakMeter;
+
+/// The maximum number of main channels telemetry is kept for.
+pub const MAX_CHANNELS: usize = 2;
+/// The number of frames kept in the history. One frame is written per processed block.
+pub const HISTORY_LEN: usize = 1024;
+
+/// A single channel's telemetry for one processed block. All levels are peak values over the block
+/// in voltage gain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelFrame {
+    /// The input level after the input gain.
+    pub input_level: f32,
+    /// The detector level after the envelope follower, which is what gets compared to the
+    /// threshold.
+    pub detector_level: f32,
+    pub output_level: f32,
+    /// The lowest gain applied by the gate during the block. The gain reduction in decibels is
+    /// `gain_to_db(gain)`.
+    pub gain: f32,
+    /// The gate's stage at the end of the block.
+    pub stage: GateStage,
+}
+
+/// Telemetry written by the plugin's `process()` function and read by the editor. There is a single
+/// writer, and readers may observe a frame that is being overwritten. That's fine for display
+/// purposes, and it keeps the audio thread free of locks.
+pub struct Telemetry {
+    /// The output peak meters, one per channel.
+    pub peak_meters: [MeterTelemetry; MAX_CHANNELS],
+    /// A threshold in decibels proposed by the auto threshold measurement, waiting to be applied
+    /// by the editor. NaN when there is no proposal.
+    proposed_threshold_db: AtomicF32,
+    /// The noise floor measured so far while the auto threshold is armed, in decibels. NaN when
+    /// nothing is being measured.
+    measured_noise_floor_db: AtomicF32,
+    /// The threshold the auto threshold would propose if it was disarmed now, in decibels. NaN
+    /// when nothing is being measured.
+    measured_threshold_db: AtomicF32,
+
+    /// The number of channels in the current audio IO layout, capped to [`MAX_CHANNELS`].
+    num_channels: AtomicUsize,
+    /// A ring buffer of [`HISTORY_LEN`] frames for every channel.
+    history: Box<[[AtomicChannelFrame; MAX_CHANNELS]]>,
+    /// The total number of frames written so far. The most recent frame lives at index
+    /// `(frames_written - 1) % HISTORY_LEN`.
+    frames_written: AtomicUsize,
+}
+
+/// A channel's output peak meter. Levels are stored as voltage gain.
+pub struct MeterTelemetry {
+    pub level: AtomicF32,
+    pub peak_hold: AtomicF32,
+    /// Set by the audio thread when the channel clips, and cleared by the editor when the user
+    /// clicks the clip indicator.
+    pub clipped: AtomicBool,
+}
+
+/// The atomic counterpart to [`ChannelFrame`].
+struct AtomicChannelFrame {
+    input_level: AtomicF32,
+    detector_level: AtomicF32,
+    output_level: AtomicF32,
+    gain: AtomicF32,
+    stage: AtomicU8,
+}
+
+impl Default for ChannelFrame {
+    fn default() -> Self {
+        Self {
+            input_level: 0.0,
+            detector_level: 0.0,
+            output_level: 0.0,
+            gain: 1.0,
+            stage: GateStage::Closed,
+        }
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            peak_meters: std::array::from_fn(|_| MeterTelemetry::default()),
+            proposed_threshold_db: AtomicF32::new(f32::NAN),
+            measured_noise_floor_db: AtomicF32::new(f32::NAN),
+            measured_threshold_db: AtomicF32::new(f32::NAN),
+
+            num_channels: AtomicUsize::new(0),
+            history: (0..HISTORY_LEN)
+                .map(|_| std::array::from_fn(|_| AtomicChannelFrame::default()))
+                .collect(),
+            frames_written: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Telemetry {
+    /// Set the number of channels telemetry is written for. Called from the plugin's
+    /// `initialize()` function. This also clears the history.
+    pub fn set_num_channels(&self, num_channels: usize) {
+        self.num_channels.store(num_channels.min(MAX_CHANNELS), Ordering::Relaxed);
+        self.frames_written.store(0, Ordering::Release);
+    }
+
+    /// The number of channels telemetry is written for.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels.load(Ordering::Relaxed)
+    }
+
+    /// Propose a new threshold in decibels. This replaces any earlier proposal the editor has not
+    /// picked up yet.
+    pub fn propose_threshold(&self, threshold_db: f32) {
+        self.proposed_threshold_db.store(threshold_db, Ordering::Relaxed);
+    }
+
+    /// Take the proposed threshold, if there is one. Only the editor should call this, since it's
+    /// the only place the parameter can be changed from.
+    pub fn take_proposed_threshold(&self) -> Option<f32> {
+        let threshold_db = self.proposed_threshold_db.swap(f32::NAN, Ordering::Relaxed);
+        if threshold_db.is_nan() { None } else { Some(threshold_db) }
+    }
+
+    /// Drop the proposed threshold, if there is one. This is done while the editor is closed, so a
+    /// stale proposal doesn't get applied when the editor is opened again.
+    pub fn clear_proposed_threshold(&self) {
+        self.proposed_threshold_db.store(f32::NAN, Ordering::Relaxed);
+    }
+
+    /// Publish the auto threshold's measurement so far as the noise floor and the threshold it
+    /// would propose, in decibels. `None` means that nothing is being measured.
+    pub fn set_auto_threshold_measurement(&self, measurement: Option<(f32, f32)>) {
+        let (noise_floor_db, threshold_db) = measurement.unwrap_or((f32::NAN, f32::NAN));
+        self.measured_noise_floor_db.store(noise_floor_db, Ordering::Relaxed);
+        self.measured_threshold_db.store(threshold_db, Ordering::Relaxed);
+    }
+
+    /// The auto threshold's measurement so far, see
+    /// [`set_auto_threshold_measurement()`][Self::set_auto_threshold_measurement()].
+    pub fn auto_threshold_measurement(&self) -> Option<(f32, f32)> {
+        let noise_floor_db = self.measured_noise_floor_db.load(Ordering::Relaxed);
+        let threshold_db = self.measured_threshold_db.load(Ordering::Relaxed);
+        if noise_floor_db.is_nan() || threshold_db.is_nan() {
+            None
+        } else {
+            Some((noise_floor_db, threshold_db))
+        }
+    }
+
+    /// Append a frame to the history. `frames` contains one frame per channel, channels past
+    /// [`MAX_CHANNELS`] are ignored. This should only be called from the audio thread.
+    pub fn push(&self, frames: &[ChannelFrame]) {
+        let frames_written = self.frames_written.load(Ordering::Relaxed);
+        let slot = &self.history[frames_written % HISTORY_LEN];
+        for (atomic_frame, frame) in slot.iter().zip(frames) {
+            atomic_frame.store(frame);
+        }
+
+        self.frames_written.store(frames_written + 1, Ordering::Release);
+    }
+
+    /// The most recent frame for a channel, if any frames have been written yet.
+    pub fn latest(&self, channel_idx: usize) -> Option<ChannelFrame> {
+        let frames_written = self.frames_written.load(Ordering::Acquire);
+        if frames_written == 0 || channel_idx >= MAX_CHANNELS {
+            return None;
+        }
+
+        Some(self.history[(frames_written - 1) % HISTORY_LEN][channel_idx].load())
+    }
+
+    /// Call `f` with a channel's frames in chronological order, oldest first. This yields at most
+    /// [`HISTORY_LEN`] frames.
+    pub fn for_each_in_history(&self, channel_idx: usize, mut f: impl FnMut(ChannelFrame)) {
+        let frames_written = self.frames_written.load(Ordering::Acquire);
+        if channel_idx >= MAX_CHANNELS {
+            return;
+        }
+
+        let first_frame = frames_written.saturating_sub(HISTORY_LEN);
+        for frame_idx in first_frame..frames_written {
+            f(self.history[frame_idx % HISTORY_LEN][channel_idx].load());
+        }
+    }
+}
+
+impl Default for MeterTelemetry {
+    fn default() -> Self {
+        Self {
+            level: AtomicF32::new(0.0),
+            peak_hold: AtomicF32::new(0.0),
+            clipped: AtomicBool::new(false),
+        }
+    }
+}
+
+impl MeterTelemetry {
+    /// Publish a meter's current state. The clip indicator is only ever set here, clearing it is up
+    /// to the editor.
+    pub fn update(&self, meter: &mut PeakMeter) {
+        self.level.store(meter.level(), Ordering::Relaxed);
+        self.peak_hold.store(meter.peak_hold(), Ordering::Relaxed);
+        if meter.take_clipped() {
+            self.clipped.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for AtomicChannelFrame {
+    fn default() -> Self {
+        Self {
+            input_level: AtomicF32::new(0.0),
+            detector_level: AtomicF32::new(0.0),
+            output_level: AtomicF32::new(0.0),
+            gain: AtomicF32::new(1.0),
+            stage: AtomicU8::new(stage_to_u8(GateStage::Closed)),
+        }
+    }
+}
+
+impl AtomicChannelFrame {
+    fn store(&self, frame: &ChannelFrame) {
+        self.input_level.store(frame.input_level, Ordering::Relaxed);
+        self.detector_level.store(frame.detector_level, Ordering::Relaxed);
+        self.output_level.store(frame.output_level, Ordering::Relaxed);
+        self.gain.store(frame.gain, Ordering::Relaxed);
+        self.stage.store(stage_to_u8(frame.stage), Ordering::Relaxed);
+    }
+
+    fn load(&self) -> ChannelFrame {
+        ChannelFrame {
+            input_level: self.input_level.load(Ordering::Relaxed),
+            detector_level: self.detector_level.load(Ordering::Relaxed),
+            output_level: self.output_level.load(Ordering::Relaxed),
+            gain: self.gain.load(Ordering::Relaxed),
+            stage: stage_from_u8(self.stage.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+fn stage_to_u8(stage: GateStage) -> u8 {
+    match stage {
+        GateStage::Closed => 0,
+        GateStage::Attack => 1,
+        GateStage::Open => 2,
+        GateStage::Hold => 3,
+        GateStage::Release => 4,
+    }
+}
+
+fn stage_from_u8(value: u8) -> GateStage {
+    match value {
+        1 => GateStage::Attack,
+        2 => GateStage::Open,
+        3 => GateStage::Hold,
+        4 => GateStage::Release,
+        _ => GateStage::Closed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame that can be told apart from the others by its input level.
+    fn frame(frame_idx: usize) -> ChannelFrame {
+        ChannelFrame {
+            input_level: frame_idx as f32,
+            stage: [GateStage::Open, GateStage::Release][frame_idx % 2],
+            ..ChannelFrame::default()
+        }
+    }
+
+    fn history(telemetry: &Telemetry, channel_idx: usize) -> Vec<ChannelFrame> {
+        let mut frames = Vec::new();
+        telemetry.for_each_in_history(channel_idx, |frame| frames.push(frame));
+
+        frames
+    }
+
+    #[test]
+    fn empty_history() {
+        let telemetry = Telemetry::default();
+        telemetry.set_num_channels(2);
+        assert_eq!(telemetry.latest(0), None);
+        assert!(history(&telemetry, 0).is_empty());
+    }
+
+    #[test]
+    fn history_is_oldest_first() {
+        let telemetry = Telemetry::default();
+        telemetry.set_num_channels(2);
+        for frame_idx in 0..3 {
+            telemetry.push(&[frame(frame_idx), frame(frame_idx + 100)]);
+        }
+
+        assert_eq!(history(&telemetry, 0), [frame(0), frame(1), frame(2)]);
+        assert_eq!(history(&telemetry, 1), [frame(100), frame(101), frame(102)]);
+        assert_eq!(telemetry.latest(0), Some(frame(2)));
+        assert_eq!(telemetry.latest(1), Some(frame(102)));
+        assert_eq!(telemetry.latest(MAX_CHANNELS), None);
+    }
+
+    #[test]
+    fn push_wraps_past_the_history_length() {
+        let telemetry = Telemetry::default();
+        telemetry.set_num_channels(1);
+        let num_frames = HISTORY_LEN + 10;
+        for frame_idx in 0..num_frames {
+            telemetry.push(&[frame(frame_idx)]);
+        }
+
+        let expected: Vec<_> = (10..num_frames).map(frame).collect();
+        assert_eq!(history(&telemetry, 0), expected);
+        assert_eq!(telemetry.latest(0), Some(frame(num_frames - 1)));
+    }
+
+    #[test]
+    fn channels_are_capped() {
+        let telemetry = Telemetry::default();
+        telemetry.set_num_channels(MAX_CHANNELS + 2);
+        assert_eq!(telemetry.num_channels(), MAX_CHANNELS);
+
+        // The extra channels are ignored
+        let frames: Vec<_> = (0..MAX_CHANNELS + 2).map(frame).collect();
+        telemetry.push(&frames);
+        for channel_idx in 0..MAX_CHANNELS {
+            assert_eq!(telemetry.latest(channel_idx), Some(frame(channel_idx)));
+        }
+        assert_eq!(history(&telemetry, MAX_CHANNELS), []);
+    }
+
+    #[test]
+    fn set_num_channels_clears_the_history() {
+        let telemetry = Telemetry::default();
+        telemetry.set_num_channels(2);
+        telemetry.push(&[frame(0), frame(1)]);
+
+        telemetry.set_num_channels(1);
+        assert_eq!(telemetry.num_channels(), 1);
+        assert_eq!(telemetry.latest(0), None);
+        assert!(history(&telemetry, 0).is_empty());
+    }
+}
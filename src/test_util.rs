@@ -0,0 +1,13 @@
+//! Helpers shared by the unit tests.
+
+/// An endless stream of white noise in `[-1, 1]`. This is a xorshift generator, so the noise is
+/// deterministic without any extra dependencies, and every stream starts with the same samples.
+pub fn noise() -> impl Iterator<Item = f32> {
+    let mut rng_state: u32 = 0x1234_5678;
+    std::iter::repeat_with(move || {
+        rng_state ^= rng_state << 13;
+        rng_state ^= rng_state >> 17;
+        rng_state ^= rng_state << 5;
+        ((rng_state as f32) / (u32::MAX as f32)) * 2.0 - 1.0
+    })
+}